@@ -0,0 +1,43 @@
+//! Feeds arbitrary lines to `frame_line` and the envelope parsers. Nothing may panic, and
+//! whatever parses must serialize back to something that parses the same.
+//! Run with `cargo +nightly fuzz run envelope` from the repository root;
+//! `tests/golden/*.jsonl` makes a good seed corpus.
+
+#![no_main]
+
+use flyio_dist::admin::AdminPayload;
+use flyio_dist::audit::Envelope;
+use flyio_dist::workloads::{broadcast, echo, kafka};
+use flyio_dist::{ErrorPayload, InitPayload, Message, WithExtra, frame_line};
+use libfuzzer_sys::fuzz_target;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+
+fn round_trips<P: Serialize + DeserializeOwned + Debug + PartialEq>(line: &[u8]) {
+    let Ok(msg) = serde_json::from_slice::<Message<P>>(line) else {
+        return;
+    };
+    let again = serde_json::to_vec(&msg).expect("a parsed message serializes");
+    let reparsed: Message<P> = serde_json::from_slice(&again).expect("serialized message parses");
+    assert_eq!(reparsed.body.payload, msg.body.payload);
+    assert_eq!((reparsed.src, reparsed.dst), (msg.src, msg.dst));
+    assert_eq!(
+        Envelope::peek(&again).map(|e| e.msg_id),
+        Some(msg.body.msg_id)
+    );
+}
+
+fuzz_target!(|data: &[u8]| {
+    // what run_node's reader does with every input line.
+    let _ = frame_line::<WithExtra<echo::Payload>>(data);
+    let _ = frame_line::<WithExtra<broadcast::Payload>>(data);
+    let _ = frame_line::<kafka::Payload>(data);
+    let _ = Envelope::peek(data);
+    let _ = serde_json::from_slice::<Message<InitPayload>>(data);
+    let _ = serde_json::from_slice::<Message<AdminPayload>>(data);
+    let _ = serde_json::from_slice::<Message<ErrorPayload>>(data);
+    round_trips::<WithExtra<echo::Payload>>(data);
+    round_trips::<WithExtra<broadcast::Payload>>(data);
+    round_trips::<kafka::Payload>(data);
+});
@@ -0,0 +1,74 @@
+//! The reserved `admin_*` message family. These are answered by `run_node`
+//! itself, so every binary exposes the same debugging hooks; workloads only
+//! fill in the `Node` hooks they care about.
+
+use crate::audit::{AuditRecord, SizeHistogram};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Every message type starting with this is routed to the library.
+pub const ADMIN_PREFIX: &str = "admin_";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminPayload {
+    AdminStats,
+    AdminStatsOk {
+        stats: Stats,
+    },
+    AdminDumpState,
+    AdminDumpStateOk {
+        state: serde_json::Value,
+        audit: Vec<AuditRecord>,
+    },
+    AdminLoadState {
+        state: serde_json::Value,
+    },
+    AdminLoadStateOk,
+    AdminSetConfig {
+        key: String,
+        value: serde_json::Value,
+    },
+    AdminSetConfigOk,
+    AdminSelfCheck,
+    AdminSelfCheckOk {
+        problems: Vec<String>,
+    },
+}
+
+/// Runtime counters kept by `run_node`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub uptime_ms: u64,
+    /// time `Node::from_init` took, e.g. reopening storage left by a
+    /// previous run.
+    pub init_ms: u64,
+    /// handled messages by body type, admin messages included.
+    pub received: BTreeMap<String, u64>,
+    /// written messages by body type.
+    pub sent: BTreeMap<String, u64>,
+    /// sizes of written messages by body type.
+    pub sent_sizes: BTreeMap<String, SizeHistogram>,
+    /// input lines that didn't deserialize.
+    pub malformed: u64,
+    /// steps that panicked.
+    pub panics: u64,
+    /// steps that returned an error.
+    pub step_errors: u64,
+    /// requests that went over the latency budget.
+    pub slow: u64,
+    /// most inputs read but not yet handled at once.
+    pub max_queue_depth: u64,
+    /// requests answered with temporarily-unavailable because they queued
+    /// too long.
+    pub shed: u64,
+    /// requests handled from clients (`c*`), admin messages excluded.
+    pub client_ops: u64,
+    /// messages written to other nodes (`n*`).
+    pub node_msgs_sent: u64,
+    /// `node_msgs_sent` per client op: this node's share of Maelstrom's
+    /// msgs-per-op.
+    pub msgs_per_op: f64,
+    /// whatever the workload reports through `Node::stats`.
+    pub node: serde_json::Value,
+}
@@ -0,0 +1,489 @@
+//! Records, for each handled message, the messages it caused to be written
+//! (replies, forwards, gossip), so fan-out can be traced after a run.
+
+use serde::ser::{self, Impossible, SerializeMap, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// How many handled messages the audit ring remembers.
+pub const AUDIT_CAPACITY: usize = 1024;
+
+/// Identifies a message on the wire.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Envelope {
+    pub src: String,
+    #[serde(rename = "dest")]
+    pub dst: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub msg_id: Option<u64>,
+    pub in_reply_to: Option<u64>,
+    /// the body's `trace_id` (see `protocol::TRACE_ID`), when it is a string.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace_id: Option<String>,
+}
+
+impl Envelope {
+    /// Reads just the envelope fields of a JSON message, `None` if `line`
+    /// isn't one.
+    pub fn peek(line: &[u8]) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Raw<'a> {
+            #[serde(borrow)]
+            src: Cow<'a, str>,
+            #[serde(borrow)]
+            dest: Cow<'a, str>,
+            #[serde(borrow)]
+            body: RawBody<'a>,
+        }
+        #[derive(Deserialize)]
+        struct RawBody<'a> {
+            #[serde(rename = "type", default, borrow)]
+            kind: Cow<'a, str>,
+            #[serde(default, deserialize_with = "crate::protocol::lenient_id")]
+            msg_id: Option<u64>,
+            #[serde(default, deserialize_with = "crate::protocol::lenient_id")]
+            in_reply_to: Option<u64>,
+            trace_id: Option<serde_json::Value>,
+        }
+        let raw: Raw = serde_json::from_slice(line).ok()?;
+        Some(Self {
+            src: raw.src.into_owned(),
+            dst: raw.dest.into_owned(),
+            kind: raw.body.kind.into_owned(),
+            msg_id: raw.body.msg_id,
+            in_reply_to: raw.body.in_reply_to,
+            trace_id: raw
+                .body
+                .trace_id
+                .and_then(|id| id.as_str().map(str::to_string)),
+        })
+    }
+}
+
+/// The `type` and string `trace_id` of `body`, read off its `Serialize`
+/// impl without writing it out: only those two fields are serialized.
+pub(crate) fn body_fields<B: Serialize + ?Sized>(body: &B) -> (String, Option<String>) {
+    let mut fields = BodyFields::default();
+    // a body that isn't an object has neither.
+    let _ = body.serialize(&mut fields);
+    (fields.kind.unwrap_or_default(), fields.trace_id)
+}
+
+#[derive(Default)]
+struct BodyFields {
+    // the key of the map entry being serialized.
+    key: Option<String>,
+    kind: Option<String>,
+    trace_id: Option<String>,
+}
+
+impl BodyFields {
+    fn field<T: Serialize + ?Sized>(&mut self, key: Option<&str>, value: &T) {
+        let slot = match key {
+            Some("type") => &mut self.kind,
+            Some(crate::protocol::TRACE_ID) => &mut self.trace_id,
+            _ => return,
+        };
+        *slot = value.serialize(StrValue).ok().flatten();
+    }
+}
+
+fn not_an_object() -> serde_json::Error {
+    ser::Error::custom("not an object")
+}
+
+// `Serializer` methods for scalars, all answering `$answer`.
+macro_rules! scalars {
+    ($answer:expr) => {
+        fn serialize_bool(self, _: bool) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_i8(self, _: i8) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_i16(self, _: i16) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_i32(self, _: i32) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_i64(self, _: i64) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_u8(self, _: u8) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_u16(self, _: u16) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_u32(self, _: u32) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_u64(self, _: u64) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_f32(self, _: f32) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_f64(self, _: f64) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_char(self, _: char) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_bytes(self, _: &[u8]) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_unit_struct(self, _: &'static str) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_unit_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+        ) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_newtype_variant<T: Serialize + ?Sized>(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: &T,
+        ) -> Result<Self::Ok, Self::Error> {
+            $answer
+        }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+            Err(not_an_object())
+        }
+        fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Self::Error> {
+            Err(not_an_object())
+        }
+        fn serialize_tuple_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+            Err(not_an_object())
+        }
+        fn serialize_tuple_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+            Err(not_an_object())
+        }
+        fn serialize_struct_variant(
+            self,
+            _: &'static str,
+            _: u32,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStructVariant, Self::Error> {
+            Err(not_an_object())
+        }
+    };
+}
+
+impl Serializer for &mut BodyFields {
+    type Ok = ();
+    type Error = serde_json::Error;
+    type SerializeSeq = Impossible<(), serde_json::Error>;
+    type SerializeTuple = Impossible<(), serde_json::Error>;
+    type SerializeTupleStruct = Impossible<(), serde_json::Error>;
+    type SerializeTupleVariant = Impossible<(), serde_json::Error>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), serde_json::Error>;
+
+    scalars!(Err(not_an_object()));
+
+    fn serialize_str(self, _: &str) -> Result<(), serde_json::Error> {
+        Err(not_an_object())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, serde_json::Error> {
+        Ok(self)
+    }
+}
+
+impl SerializeMap for &mut BodyFields {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), serde_json::Error> {
+        self.key = key.serialize(StrValue).ok().flatten();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(
+        &mut self,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        let key = self.key.take();
+        self.field(key.as_deref(), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
+}
+
+impl SerializeStruct for &mut BodyFields {
+    type Ok = ();
+    type Error = serde_json::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), serde_json::Error> {
+        self.field(Some(key), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), serde_json::Error> {
+        Ok(())
+    }
+}
+
+// serializes a string to itself and any other scalar to `None`.
+struct StrValue;
+
+impl Serializer for StrValue {
+    type Ok = Option<String>;
+    type Error = serde_json::Error;
+    type SerializeSeq = Impossible<Option<String>, serde_json::Error>;
+    type SerializeTuple = Impossible<Option<String>, serde_json::Error>;
+    type SerializeTupleStruct = Impossible<Option<String>, serde_json::Error>;
+    type SerializeTupleVariant = Impossible<Option<String>, serde_json::Error>;
+    type SerializeMap = Impossible<Option<String>, serde_json::Error>;
+    type SerializeStruct = Impossible<Option<String>, serde_json::Error>;
+    type SerializeStructVariant = Impossible<Option<String>, serde_json::Error>;
+
+    scalars!(Ok(None));
+
+    fn serialize_str(self, value: &str) -> Result<Option<String>, serde_json::Error> {
+        Ok(Some(value.to_string()))
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(
+        self,
+        value: &T,
+    ) -> Result<Option<String>, serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Option<String>, serde_json::Error> {
+        value.serialize(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, serde_json::Error> {
+        Err(not_an_object())
+    }
+
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, serde_json::Error> {
+        Err(not_an_object())
+    }
+}
+
+/// One handled message and everything written while handling it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub cause: Envelope,
+    pub effects: Vec<Envelope>,
+}
+
+/// Sizes of written lines, in bytes, newline excluded. Bucket `b` counts
+/// lines of up to `b` bytes that didn't fit the previous (half as big) one.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct SizeHistogram {
+    pub count: u64,
+    pub total: u64,
+    pub max: u64,
+    pub buckets: BTreeMap<u64, u64>,
+}
+
+impl SizeHistogram {
+    pub fn record(&mut self, size: u64) {
+        self.count += 1;
+        self.total += size;
+        self.max = self.max.max(size);
+        *self.buckets.entry(size.next_power_of_two()).or_default() += 1;
+    }
+}
+
+/// Wraps the node's output: every line written between `begin` and `end` is
+/// attributed to the message being handled, and the last `AUDIT_CAPACITY`
+/// records are kept.
+pub struct AuditWriter<W> {
+    inner: W,
+    line: Vec<u8>,
+    current: Option<AuditRecord>,
+    ring: VecDeque<AuditRecord>,
+    sent: BTreeMap<String, u64>,
+    sent_to_nodes: u64,
+    sent_sizes: BTreeMap<String, SizeHistogram>,
+    write_time: Duration,
+}
+
+impl<W: Write> AuditWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: Vec::new(),
+            current: None,
+            ring: VecDeque::with_capacity(AUDIT_CAPACITY),
+            sent: BTreeMap::new(),
+            sent_to_nodes: 0,
+            sent_sizes: BTreeMap::new(),
+            write_time: Duration::ZERO,
+        }
+    }
+
+    pub fn begin(&mut self, cause: Envelope) {
+        self.current = Some(AuditRecord {
+            cause,
+            effects: Vec::new(),
+        });
+    }
+
+    pub fn end(&mut self) {
+        if let Some(record) = self.current.take() {
+            if self.ring.len() == AUDIT_CAPACITY {
+                self.ring.pop_front();
+            }
+            self.ring.push_back(record);
+        }
+    }
+
+    /// The message being handled, between `begin` and `end`.
+    pub fn cause(&self) -> Option<&Envelope> {
+        self.current.as_ref().map(|r| &r.cause)
+    }
+
+    /// Oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &AuditRecord> {
+        self.ring.iter()
+    }
+
+    /// Number of lines written so far, by body type, whether or not a cause
+    /// was active.
+    pub fn sent(&self) -> &BTreeMap<String, u64> {
+        &self.sent
+    }
+
+    /// Number of lines written to other nodes, i.e. to destinations named
+    /// `n*` (clients are `c*`, services like `lin-kv` are neither).
+    pub fn sent_to_nodes(&self) -> u64 {
+        self.sent_to_nodes
+    }
+
+    /// Sizes of the lines written so far, by body type.
+    pub fn sent_sizes(&self) -> &BTreeMap<String, SizeHistogram> {
+        &self.sent_sizes
+    }
+
+    /// Total time spent in the inner writer's `write` and `flush`.
+    pub fn write_time(&self) -> Duration {
+        self.write_time
+    }
+
+    /// Writes `line`, the serialized message `envelope` with its newline,
+    /// and records it without parsing it back. Lines written through `Write`
+    /// instead are parsed.
+    pub fn write_message(&mut self, envelope: Envelope, line: &[u8]) -> std::io::Result<()> {
+        let started = Instant::now();
+        let res = self.inner.write_all(line);
+        self.write_time += started.elapsed();
+        res?;
+        self.record(envelope, line.len().saturating_sub(1));
+        Ok(())
+    }
+
+    /// Counts a written message of `len` bytes, newline excluded, and
+    /// attributes it to the message being handled, if any.
+    pub fn record(&mut self, effect: Envelope, len: usize) {
+        *self.sent.entry(effect.kind.clone()).or_default() += 1;
+        self.sent_sizes
+            .entry(effect.kind.clone())
+            .or_default()
+            .record(len as u64);
+        if effect.dst.starts_with('n') {
+            self.sent_to_nodes += 1;
+        }
+        if let Some(current) = self.current.as_mut() {
+            current.effects.push(effect);
+        }
+    }
+
+    fn observe(&mut self, buf: &[u8]) {
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            if let Some(line) = chunk.strip_suffix(b"\n") {
+                self.line.extend_from_slice(line);
+                if let Some(effect) = Envelope::peek(&self.line) {
+                    self.record(effect, self.line.len());
+                }
+                self.line.clear();
+            } else {
+                self.line.extend_from_slice(chunk);
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for AuditWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let started = Instant::now();
+        let n = self.inner.write(buf);
+        self.write_time += started.elapsed();
+        let n = n?;
+        self.observe(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let started = Instant::now();
+        let res = self.inner.flush();
+        self.write_time += started.elapsed();
+        res
+    }
+}
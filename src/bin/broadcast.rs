@@ -1,35 +1,42 @@
-use std::{collections::HashMap, vec};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+    vec,
+};
 
 use anyhow::Context;
+use flyio_dist::workloads::broadcast::{Messages, Payload};
 use flyio_dist::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum Payload {
-    Broadcast {
-        message: usize,
-    },
-    BroadcastOk,
-    Read,
-    ReadOk {
-        messages: Vec<usize>,
-    },
-    Topology {
-        topology: HashMap<String, Vec<String>>,
-    },
-    TopologyOk,
+fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// Latency of the forwards this node received, from their `sent_at`.
+#[derive(Debug, Default, Serialize)]
+struct HopStats {
+    hops: u64,
+    total_us: u64,
+    max_us: u64,
 }
 
 struct BroadcastNode {
     id: String,
     node_ids: Vec<String>,
-    msg_id_seq: usize,
+    msg_id_seq: u64,
+    // in insertion order, so a message's index doubles as the version it was added at.
     seen_messages: Vec<usize>,
+    // the same messages, to drop ones already seen.
+    seen: HashSet<usize>,
     topology: HashMap<String, Vec<String>>,
+    hop_stats: HopStats,
 }
 
-impl Node<(), Payload> for BroadcastNode {
+impl Node<(), WithExtra<Payload>> for BroadcastNode {
     fn from_init(_init_state: (), init: Init) -> anyhow::Result<Self>
     where
         Self: Sized,
@@ -39,50 +46,99 @@ impl Node<(), Payload> for BroadcastNode {
             node_ids: init.node_ids,
             msg_id_seq: 1,
             seen_messages: vec![],
+            seen: HashSet::new(),
             topology: HashMap::new(),
+            hop_stats: HopStats::default(),
         };
         Ok(node)
     }
 
     fn step(
         &mut self,
-        input: Message<Payload>,
-        writer: &mut std::io::StdoutLock,
-    ) -> anyhow::Result<()>
-    where
-        Payload: Clone,
-    {
-        let mut reply = input.clone().to_reply(Some(&mut self.msg_id_seq));
-        match reply.body.payload {
-            Payload::Broadcast { message } => {
-                for node in &self.node_ids {
-                    if node == &self.id {
-                        continue;
+        input: Message<WithExtra<Payload>>,
+        writer: &mut AuditWriter<impl std::io::Write>,
+    ) -> anyhow::Result<()> {
+        let src = input.src.clone();
+        let mut reply = input.to_reply(Some(&mut self.msg_id_seq));
+        match reply.body.payload.payload {
+            Payload::Broadcast { messages, sent_at } => {
+                if let Some(sent_at) = sent_at {
+                    let hop = now_micros().saturating_sub(sent_at);
+                    self.hop_stats.hops += 1;
+                    self.hop_stats.total_us += hop;
+                    self.hop_stats.max_us = self.hop_stats.max_us.max(hop);
+                }
+                let new: Vec<usize> = messages
+                    .as_slice()
+                    .iter()
+                    .copied()
+                    .filter(|m| self.seen.insert(*m))
+                    .collect();
+                self.seen_messages.extend_from_slice(&new);
+
+                // the node a client picked sends the new messages to every
+                // other node itself, so forwards aren't forwarded again. They
+                // have no msg_id: peers don't acknowledge them.
+                let from_peer = self.node_ids.contains(&src);
+                if !from_peer && !new.is_empty() {
+                    // forwards keep any extra fields of the broadcast, and a
+                    // client's broadcast gets a trace id naming it.
+                    let mut extra = std::mem::take(&mut reply.body.payload.extra);
+                    if let Some(msg_id) = reply.body.in_reply_to {
+                        extra
+                            .entry(TRACE_ID)
+                            .or_insert_with(|| format!("{src}:{msg_id}").into());
                     }
-                    let mut m = input.clone();
-                    m.dst = node.clone();
-                    m.send(writer)
+                    let forward = Body {
+                        msg_id: None,
+                        in_reply_to: None,
+                        payload: WithExtra {
+                            payload: Payload::Broadcast {
+                                messages: match new[..] {
+                                    [message] => Messages::One { message },
+                                    _ => Messages::Many { messages: new },
+                                },
+                                sent_at: Some(now_micros()),
+                            },
+                            extra,
+                        },
+                    };
+                    let peers = self
+                        .node_ids
+                        .iter()
+                        .map(String::as_str)
+                        .filter(|node| *node != self.id);
+                    send_to_many(writer, &self.id, peers, &forward)
                         .context("failed to broadcast messages to the nodes")?;
                 }
 
-                self.seen_messages.push(message);
-
-                reply.body.payload = Payload::BroadcastOk;
-                reply
-                    .send(writer)
-                    .context("failed to write msg to std out, broadcast ok")?;
+                if reply.body.in_reply_to.is_some() {
+                    reply.body.payload = Payload::BroadcastOk.into();
+                    reply
+                        .send(writer)
+                        .context("failed to write msg to std out, broadcast ok")?;
+                }
             }
-            Payload::Read => {
-                reply.body.payload = Payload::ReadOk {
-                    messages: self.seen_messages.clone(),
+            Payload::Read { read_since } => {
+                let version = self.seen_messages.len();
+                let read_ok = match read_since {
+                    None => Payload::ReadOk {
+                        messages: self.seen_messages.clone(),
+                        version: None,
+                    },
+                    Some(since) => Payload::ReadOk {
+                        messages: self.seen_messages[since.min(version)..].to_vec(),
+                        version: Some(version),
+                    },
                 };
+                reply.body.payload = read_ok.into();
                 reply
                     .send(writer)
                     .context("failed to write msg to stdout, read ok")?;
             }
             Payload::Topology { topology } => {
                 self.topology = topology;
-                reply.body.payload = Payload::TopologyOk;
+                reply.body.payload = Payload::TopologyOk.into();
                 reply
                     .send(writer)
                     .context("faield to write msg to stdout, topologyok")?;
@@ -91,9 +147,49 @@ impl Node<(), Payload> for BroadcastNode {
         }
         Ok(())
     }
+
+    fn stats(&self) -> serde_json::Value {
+        let mean_us = match self.hop_stats.hops {
+            0 => 0,
+            hops => self.hop_stats.total_us / hops,
+        };
+        serde_json::json!({ "hop_latency": self.hop_stats, "mean_hop_us": mean_us })
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "node_ids": self.node_ids,
+            "seen_messages": self.seen_messages,
+            "topology": self.topology,
+        })
+    }
+
+    fn load_state(&mut self, state: &serde_json::Value) -> anyhow::Result<bool> {
+        // the parts of `debug_state` that are the node's state, not its identity.
+        #[derive(Deserialize)]
+        struct Snapshot {
+            seen_messages: Vec<usize>,
+            topology: HashMap<String, Vec<String>>,
+        }
+        let snapshot = Snapshot::deserialize(state).context("parse broadcast state")?;
+        self.seen.clear();
+        self.seen_messages = snapshot
+            .seen_messages
+            .into_iter()
+            .filter(|m| self.seen.insert(*m))
+            .collect();
+        self.topology = snapshot.topology;
+        Ok(true)
+    }
+
+    // a read can be retried; a broadcast has to be acknowledged.
+    fn sheddable(&self, message: &Message<WithExtra<Payload>>) -> bool {
+        matches!(message.body.payload.payload, Payload::Read { .. })
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), BroadcastNode, Payload>(())?;
+    main_loop::<(), BroadcastNode, WithExtra<Payload>>(())?;
     Ok(())
 }
@@ -0,0 +1,118 @@
+//! Compares two runs: `compare_stats <before> <after>`. Each side is a file
+//! holding an `admin_stats_ok` reply (the whole message, its body, or just
+//! `stats`), an end-of-run summary, or a trace written with the `trace` feature; the report lists
+//! every metric both sides have.
+
+use anyhow::Context;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+fn percentile(sorted: &[u64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[rank] as f64
+}
+
+// step and queue time percentiles of a Chrome trace, in µs.
+fn trace_metrics(events: &[Value]) -> BTreeMap<String, f64> {
+    let mut durations: BTreeMap<&str, Vec<u64>> = BTreeMap::new();
+    for event in events {
+        if let (Some(cat), Some(dur)) = (event["cat"].as_str(), event["dur"].as_u64()) {
+            durations.entry(cat).or_default().push(dur);
+        }
+    }
+    let mut metrics = BTreeMap::new();
+    for (cat, mut durs) in durations {
+        durs.sort_unstable();
+        metrics.insert(format!("{cat}_us_p50"), percentile(&durs, 0.5));
+        metrics.insert(format!("{cat}_us_p99"), percentile(&durs, 0.99));
+        metrics.insert(format!("{cat}_count"), durs.len() as f64);
+    }
+    metrics
+}
+
+fn stats_metrics(stats: &Value) -> BTreeMap<String, f64> {
+    let mut metrics = BTreeMap::new();
+    for key in [
+        "msgs_per_op",
+        "client_ops",
+        "node_msgs_sent",
+        "slow",
+        "shed",
+        "malformed",
+        "panics",
+        "step_errors",
+        "init_ms",
+        "max_queue_depth",
+    ] {
+        if let Some(v) = stats[key].as_f64() {
+            metrics.insert(key.to_string(), v);
+        }
+    }
+    if let Some(sizes) = stats["sent_sizes"].as_object() {
+        let bytes = sizes.values().filter_map(|h| h["total"].as_f64()).sum();
+        metrics.insert("bytes_sent".to_string(), bytes);
+    }
+    // workload counters, where the node reports them.
+    for (name, pointer) in [
+        ("fsyncs", "/node/sync/syncs"),
+        ("disk_fsyncs", "/node/io/fsyncs"),
+        ("disk_written", "/node/io/bytes_written"),
+        ("disk_read", "/node/io/bytes_read"),
+        ("mean_hop_us", "/node/mean_hop_us"),
+        ("clock_behind", "/node/ids/clock_skew/behind"),
+        ("clock_stalls", "/node/ids/clock_skew/stalls"),
+        ("ids_rejected", "/node/ids/clock_skew/rejected"),
+    ] {
+        if let Some(v) = stats.pointer(pointer).and_then(Value::as_f64) {
+            metrics.insert(name.to_string(), v);
+        }
+    }
+    metrics
+}
+
+fn load(path: &str) -> anyhow::Result<BTreeMap<String, f64>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {path}"))?;
+    let mut value: Value = serde_json::from_str(&text).with_context(|| format!("parse {path}"))?;
+    if let Some(events) = value["traceEvents"].as_array() {
+        return Ok(trace_metrics(events));
+    }
+    for wrapper in ["body", "stats"] {
+        if value.get(wrapper).is_some() {
+            value = value[wrapper].take();
+        }
+    }
+    anyhow::ensure!(
+        value.get("msgs_per_op").is_some(),
+        "{path} is neither admin stats nor a trace"
+    );
+    Ok(stats_metrics(&value))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [before, after] = args.as_slice() else {
+        anyhow::bail!("usage: compare_stats <before> <after>");
+    };
+    let (before, after) = (load(before)?, load(after)?);
+    println!(
+        "{:<16} {:>12} {:>12} {:>9}",
+        "metric", "before", "after", "change"
+    );
+    for (name, old) in &before {
+        let Some(new) = after.get(name) else {
+            continue;
+        };
+        let change = if *old != 0.0 {
+            format!("{:+.1}%", (new - old) / old * 100.0)
+        } else if *new == 0.0 {
+            "0.0%".to_string()
+        } else {
+            "new".to_string()
+        };
+        println!("{name:<16} {old:>12.2} {new:>12.2} {change:>9}");
+    }
+    Ok(())
+}
@@ -1,19 +1,12 @@
+use flyio_dist::workloads::echo::Payload;
 use flyio_dist::*;
-use serde::{Deserialize, Serialize};
-use std::io::StdoutLock;
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum Payload {
-    Echo { echo: String },
-    EchoOk { echo: String },
-}
+use std::io::Write;
 
 struct EchoNode {
-    id: usize,
+    id: u64,
 }
 
-impl Node<(), Payload> for EchoNode {
+impl Node<(), WithExtra<Payload>> for EchoNode {
     fn from_init(_state: (), _init: Init) -> anyhow::Result<Self>
     where
         Self: Sized,
@@ -21,12 +14,17 @@ impl Node<(), Payload> for EchoNode {
         Ok(Self { id: 1 })
     }
 
-    fn step(&mut self, input: Message<Payload>, writer: &mut StdoutLock) -> anyhow::Result<()> {
+    fn step(
+        &mut self,
+        input: Message<WithExtra<Payload>>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        // the reply keeps any extra fields of the request.
         let mut reply = input.to_reply(Some(&mut self.id));
-        match reply.body.payload {
-            Payload::Echo { echo } => reply.body.payload = Payload::EchoOk { echo },
-
-            _ => {}
+        match reply.body.payload.payload {
+            Payload::Echo { echo } => reply.body.payload.payload = Payload::EchoOk { echo },
+            // a stray echo_ok isn't answered.
+            Payload::EchoOk { .. } => return Ok(()),
         }
         reply.send(writer)?;
         Ok(())
@@ -1,315 +1,371 @@
-use glob::glob;
-use log;
 use simplelog::*;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{BufReader, Write, prelude::*};
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
 
 use anyhow::Context;
+use flyio_dist::storage::{self, FileStorage, MemStorage, Storage};
+use flyio_dist::workloads::kafka::Payload;
 use flyio_dist::*;
-use serde::{Deserialize, Serialize};
-use std::sync::atomic::AtomicUsize;
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-#[serde(rename_all = "snake_case", tag = "type")]
-enum Payload {
-    Send {
-        #[serde(rename = "key")]
-        topic: String,
-        #[serde(rename = "msg")]
-        message: usize,
-    },
-    SendOk {
-        offset: usize,
-    },
-    Poll {
-        offsets: HashMap<String, usize>,
-    },
-    PollOk {
-        #[serde(rename = "msgs")]
-        messages: HashMap<String, Vec<(usize, usize)>>,
-    },
-    CommitOffsets {
-        offsets: HashMap<String, usize>,
-    },
-    CommitOffsetsOk,
-    ListCommittedOffsets {
-        keys: Vec<String>,
-    },
-    ListCommittedOffsetsOk {
-        offsets: HashMap<String, usize>,
-    },
+use serde::Serialize;
+
+/// Whose committed offsets a commit_offsets request updates.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum CommitScope {
+    /// one committed offset per key, shared by every client (the workload's semantics).
+    #[default]
+    Global,
+    /// committed offsets tracked separately for each requesting client.
+    PerClient,
 }
 
-#[derive(Debug)]
-struct FileHandle {
-    r: File,
-    w: std::io::BufWriter<File>,
+/// Where topic logs and committed offsets are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum StorageBackend {
+    /// local files that survive a restart.
+    #[default]
+    File,
+    /// in memory only, no disk I/O at all; for measuring the cost of the file path.
+    Memory,
 }
 
-struct KafkaNode {
-    id: String,
-    node_ids: Vec<String>,
-    msg_id_seq: usize,
-
-    // keys in fly-io kafka workload
-    topics: Vec<String>,
-    next_offsets: HashMap<String, AtomicUsize>,
-    file_handles: HashMap<String, FileHandle>,
-    // index for message offset -> file_ptr
-    index: HashMap<String, HashMap<usize, u64>>,
+/// When appended messages are fsynced.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum SyncMode {
+    /// never: an acked send survives a node crash but not a machine crash.
+    #[default]
+    Never,
+    /// group commit: send_oks are held until one fsync covers the batch,
+    /// which happens once `max_batch` are waiting or the input queue drains.
+    Group { max_batch: usize },
 }
 
-impl KafkaNode {
-    fn get_or_create_log_file(
-        &mut self,
-        topic: &str,
-    ) -> anyhow::Result<(&mut FileHandle, &mut AtomicUsize)> {
-        if !self.file_handles.contains_key(topic) {
-            let str_path = format!("{}-{}.log", self.id, topic);
-            let path = Path::new(&str_path);
-
-            if let Some(parent) = path.parent() {
-                create_dir_all(parent).context("create all dir, file handles")?; // idempotent: OK if it already exists
-            }
-            // Open for read/write; create if missing
-            let w = OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&path)
-                .context("open, append only write log file")?;
-            let r = OpenOptions::new()
-                .read(true)
-                .open(&path)
-                .context("open, read only log")?;
-
-            // Seek once to the end to initialize the next offset
-            if !self.next_offsets.contains_key(topic) {
-                self.next_offsets
-                    .insert(topic.to_string(), AtomicUsize::new(0));
-            }
+/// Default `KAFKA_SYNC_BATCH`.
+const DEFAULT_SYNC_BATCH: usize = 64;
+
+/// Default `KAFKA_HOT_SHARE`.
+const DEFAULT_HOT_SHARE: f64 = 0.5;
+
+/// Startup options, read from the environment since maelstrom doesn't pass
+/// arguments to the node binaries.
+#[derive(Debug, Clone)]
+struct KafkaConfig {
+    /// `KAFKA_COMMIT_SCOPE`: `global` (default) or `client`.
+    commit_scope: CommitScope,
+    /// `KAFKA_STORAGE`: `file` (default) or `memory`.
+    storage: StorageBackend,
+    /// `KAFKA_MAX_POLL_BYTES`: rough cap on the size of a poll_ok's messages;
+    /// unset means no cap.
+    max_poll_bytes: Option<usize>,
+    /// `KAFKA_SYNC`: `none` (default) or `group`, with the batch size from
+    /// `KAFKA_SYNC_BATCH`.
+    sync: SyncMode,
+    /// `KAFKA_HOT_SHARE`: share of recent sends above which a topic is
+    /// reported hot.
+    hot_share: f64,
+}
 
-            self.file_handles.insert(
-                topic.to_string(),
-                FileHandle {
-                    r,
-                    w: std::io::BufWriter::new(w),
-                },
-            );
+impl Default for KafkaConfig {
+    fn default() -> Self {
+        Self {
+            commit_scope: CommitScope::default(),
+            storage: StorageBackend::default(),
+            max_poll_bytes: None,
+            sync: SyncMode::default(),
+            hot_share: DEFAULT_HOT_SHARE,
         }
-        Ok((
-            self.file_handles.get_mut(topic).unwrap(),
-            self.next_offsets.get_mut(topic).unwrap(),
-        ))
     }
+}
 
-    fn build_index(
-        node_id: &str,
-    ) -> anyhow::Result<(
-        HashMap<String, HashMap<usize, u64>>,
-        HashMap<String, AtomicUsize>,
-    )> {
-        let pattern = format!("{}-*.log", node_id);
-
-        let mut index: HashMap<String, HashMap<usize, u64>> = HashMap::new();
-        let mut next_offsets = HashMap::new();
-
-        for path_entry in glob(&pattern).expect("invalid glob pattern") {
-            match path_entry {
-                Ok(path) => {
-                    if path.is_file() {
-                        let readf = File::open(&path).context("build index, read file")?;
-                        let mut reader = BufReader::new(readf);
-
-                        // too much confidence in directory structures
-                        let stem = path.file_stem().unwrap().to_str().unwrap();
-                        let topic = stem.strip_prefix(&format!("{}-", node_id)).unwrap();
-                        let mut location_ptr = 0u64;
-                        let mut buf = String::new();
-                        let mut next_offset = 0;
-                        loop {
-                            buf.clear();
-                            let n = reader.read_line(&mut buf)?;
-                            if n == 0 {
-                                break;
-                            }
-                            let log_entry: LogEntry = serde_json::from_str(buf.trim_end())?;
-                            if log_entry.offset > next_offset {
-                                next_offset = log_entry.offset;
-                            }
-
-                            index
-                                .entry(topic.to_string())
-                                .or_default()
-                                .insert(log_entry.offset, location_ptr);
-                            location_ptr += n as u64;
-                        }
-                        next_offsets.insert(topic.to_string(), AtomicUsize::new(next_offset + 1));
-                    }
+impl KafkaConfig {
+    fn from_env() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(scope) = std::env::var("KAFKA_COMMIT_SCOPE") {
+            config.commit_scope = match scope.as_str() {
+                "global" => CommitScope::Global,
+                "client" => CommitScope::PerClient,
+                other => anyhow::bail!("unknown KAFKA_COMMIT_SCOPE {other:?}"),
+            };
+        }
+        if let Ok(storage) = std::env::var("KAFKA_STORAGE") {
+            config.storage = match storage.as_str() {
+                "file" => StorageBackend::File,
+                "memory" => StorageBackend::Memory,
+                other => anyhow::bail!("unknown KAFKA_STORAGE {other:?}"),
+            };
+        }
+        if let Ok(max) = std::env::var("KAFKA_MAX_POLL_BYTES") {
+            let max = max
+                .parse()
+                .with_context(|| format!("bad KAFKA_MAX_POLL_BYTES {max:?}"))?;
+            config.max_poll_bytes = Some(max);
+        }
+        if let Ok(sync) = std::env::var("KAFKA_SYNC") {
+            config.sync = match sync.as_str() {
+                "none" => SyncMode::Never,
+                "group" => {
+                    let max_batch = match std::env::var("KAFKA_SYNC_BATCH") {
+                        Ok(n) => n
+                            .parse()
+                            .with_context(|| format!("bad KAFKA_SYNC_BATCH {n:?}"))?,
+                        Err(_) => DEFAULT_SYNC_BATCH,
+                    };
+                    anyhow::ensure!(max_batch > 0, "KAFKA_SYNC_BATCH must be at least 1");
+                    SyncMode::Group { max_batch }
                 }
-                Err(e) => eprintln!("glob error: {}", e),
-            }
+                other => anyhow::bail!("unknown KAFKA_SYNC {other:?}"),
+            };
+        }
+        if let Ok(share) = std::env::var("KAFKA_HOT_SHARE") {
+            let share: f64 = share
+                .parse()
+                .with_context(|| format!("bad KAFKA_HOT_SHARE {share:?}"))?;
+            anyhow::ensure!(
+                share > 0.0 && share <= 1.0,
+                "KAFKA_HOT_SHARE must be in (0, 1]"
+            );
+            config.hot_share = share;
         }
-        Ok((index, next_offsets))
+        Ok(config)
     }
+}
 
-    fn update_index(&mut self, topic: &str, current_offset: usize, file_loc_ptr: u64) {
-        if let Some(entry) = self.index.get_mut(topic) {
-            entry.insert(current_offset, file_loc_ptr);
-        } else {
-            self.index.insert(topic.to_string(), HashMap::new());
-            let Some(entry) = self.index.get_mut(topic) else {
-                panic!("unreachable");
+/// Cuts each topic's messages down to a prefix so the whole fits in about
+/// `max_bytes` of JSON, taking from the topics in turn so none is starved.
+/// A poll_ok only has to return messages from the requested offsets on, so a
+/// shorter one is still valid: the client polls again from where it stopped.
+/// At least one message is kept so a poll always makes progress.
+fn trim_poll(messages: &mut HashMap<String, Vec<(usize, usize)>>, max_bytes: usize) -> usize {
+    // `"topic":[]` plus a comma, and `[offset,msg]` plus a comma.
+    let digits = |n: usize| n.checked_ilog10().unwrap_or(0) as usize + 1;
+    let mut used: usize = messages.keys().map(|t| t.len() + 6).sum();
+    let mut topics: Vec<String> = messages.keys().cloned().collect();
+    topics.sort_unstable();
+    let mut keep = vec![0; topics.len()];
+    let mut kept = 0;
+    'fill: for i in 0.. {
+        let mut any = false;
+        for (t, topic) in topics.iter().enumerate() {
+            let Some(&(offset, msg)) = messages[topic].get(i) else {
+                continue;
             };
-            entry.insert(current_offset, file_loc_ptr);
+            any = true;
+            let size = digits(offset) + digits(msg) + 4;
+            if kept > 0 && used + size > max_bytes {
+                break 'fill;
+            }
+            used += size;
+            kept += 1;
+            keep[t] += 1;
+        }
+        if !any {
+            break;
         }
     }
-
-    fn append_message(&mut self, topic: &str, message: usize) -> anyhow::Result<usize> {
-        let (fh, offset) = self
-            .get_or_create_log_file(topic)
-            .context("open/seek file")?;
-        let current_offset = *offset.get_mut();
-        let entry = LogEntry {
-            offset: current_offset,
-            message,
-        };
-        let start_ptr = fh.r.metadata()?.len();
-
-        *offset.get_mut() += 1; // increment the atomic counter of msg offsets
-        // this will append we can we have opened the file in append mode.
-
-        writeln!(fh.w, "{}", serde_json::to_string(&entry)?)?;
-
-        // update the index with start ptr of current message.
-        self.update_index(topic, current_offset, start_ptr);
-        Ok(current_offset)
+    let mut trimmed = 0;
+    for (topic, n) in topics.iter().zip(keep) {
+        let vals = messages.get_mut(topic).expect("topic from the map");
+        trimmed += vals.len() - n;
+        vals.truncate(n);
     }
+    trimmed
+}
 
-    fn read_messages(
-        &mut self,
-        topic: &str,
-        start_message_offset: usize,
-    ) -> anyhow::Result<Vec<(usize, usize)>> {
-        let Some(entry) = self.index.get(topic) else {
-            // we don't even have this topic, so offset is definitely not there
-            return Ok(vec![]);
-        };
-
-        let Some(pos) = entry.get(&start_message_offset) else {
-            // we have not processed this entry yet.
-            return Ok(vec![]);
-        };
-        let pos = *pos;
-        let (fh, _) = self.get_or_create_log_file(topic)?;
-        fh.w.flush()?; // flushing before starting to read.
-        let rf = &fh.r;
-        let mut reader = BufReader::new(rf);
+/// Group commit counters, reported by `admin_stats`.
+#[derive(Debug, Default, Serialize)]
+struct SyncStats {
+    syncs: u64,
+    failed_syncs: u64,
+    synced_sends: u64,
+    max_batch: usize,
+}
 
-        reader.seek(std::io::SeekFrom::Start(pos))?;
+/// Sends per topic are counted until this many have been seen, then every
+/// count is halved, so the shares follow recent load.
+const HOT_WINDOW: u64 = 1024;
+/// Fewer recent sends than this say nothing about skew.
+const HOT_MIN_SENDS: u64 = 100;
+
+/// Spots topics taking most of the sends, reported by `admin_stats`.
+#[derive(Debug, Default)]
+struct HotKeys {
+    recent: HashMap<String, u64>,
+    total: u64,
+    hot: BTreeSet<String>,
+    became_hot: u64,
+}
 
-        let mut out = Vec::new();
-        let mut started = false;
-        for line in reader.lines() {
-            let line = line?;
-            if line.trim().is_empty() {
-                continue;
+impl HotKeys {
+    fn record(&mut self, topic: &str, share: f64) {
+        // only a topic's first send in the window allocates its key.
+        match self.recent.get_mut(topic) {
+            Some(n) => *n += 1,
+            None => {
+                self.recent.insert(topic.to_string(), 1);
             }
-            let entry: LogEntry = serde_json::from_str(&line)?;
-            if !started {
-                if entry.offset >= start_message_offset {
-                    out.push(entry);
-                }
-                started = true;
-            } else {
-                out.push(entry);
+        }
+        self.total += 1;
+        if self.total >= HOT_WINDOW {
+            self.recent.values_mut().for_each(|n| *n /= 2);
+            self.recent.retain(|_, n| *n > 0);
+            self.total = self.recent.values().sum();
+            let topics: Vec<String> = self.recent.keys().cloned().collect();
+            self.hot.retain(|t| self.recent.contains_key(t));
+            for topic in topics {
+                self.update(&topic, share);
             }
+        } else {
+            self.update(topic, share);
         }
-        let out: Vec<(usize, usize)> = out
-            .iter()
-            .map(|e| (e.offset, e.message))
-            .collect::<Vec<_>>();
-
-        Ok(out)
     }
 
-    fn commit(&mut self, topic: &str, commit_offset: usize) -> anyhow::Result<()> {
-        let str_path = format!("{}-{}", self.id, topic);
+    fn share(&self, topic: &str) -> f64 {
+        self.recent
+            .get(topic)
+            .map_or(0.0, |n| *n as f64 / self.total as f64)
+    }
 
-        let path = Path::new(&str_path);
-        if let Some(parent) = path.parent() {
-            create_dir_all(parent).context("unable to create all dir")?; // idempotent: OK if it already exists
+    fn update(&mut self, topic: &str, share: f64) {
+        let hot = self.total >= HOT_MIN_SENDS && self.share(topic) >= share;
+        if hot && !self.hot.contains(topic) {
+            self.hot.insert(topic.to_string());
+            self.became_hot += 1;
+            log::info!(
+                "topic {} is hot: {:.0}% of recent sends",
+                topic,
+                self.share(topic) * 100.0
+            );
+        } else if !hot && self.hot.remove(topic) {
+            log::info!("topic {} cooled down", topic);
         }
+    }
 
-        std::fs::write(path, format!("{commit_offset}\n")).context("write commit to file")?;
-        Ok(())
+    fn stats(&self) -> serde_json::Value {
+        let mut top: Vec<(&String, f64)> = self.recent.keys().map(|t| (t, self.share(t))).collect();
+        top.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+        top.truncate(5);
+        serde_json::json!({ "hot": self.hot, "became_hot": self.became_hot, "top": top })
     }
+}
 
-    fn read_commit(&mut self, topic: &str) -> Option<usize> {
-        let path = format!("{}-{}", self.id, topic);
-        let s = match std::fs::read_to_string(path).context("read from commit file") {
-            Ok(s) => Some(s),
-            Err(_) => None,
-        };
-        if s.is_some() {
-            return Some(
-                s.unwrap()
-                    .trim()
-                    .parse()
-                    .expect("invalid integer in commit file"),
-            );
+struct KafkaNode {
+    msg_id_seq: u64,
+    config: KafkaConfig,
+    storage: Box<dyn Storage>,
+    // send_oks waiting for the next fsync.
+    unsynced: Vec<Message<Payload>>,
+    sync_stats: SyncStats,
+    hot_keys: HotKeys,
+}
+
+impl KafkaNode {
+    // key of a committed offset in the storage's commit store.
+    fn commit_key(&self, topic: &str, client: &str) -> String {
+        match self.config.commit_scope {
+            CommitScope::Global => topic.to_string(),
+            CommitScope::PerClient => format!("{}@{}", topic, client),
         }
-        None
     }
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-struct LogEntry {
-    offset: usize,
-    message: usize,
+    // fsyncs the log and releases the send_oks it covers. If the fsync fails
+    // the sends may or may not have been kept, so they get a crash error,
+    // which the client treats as indefinite.
+    fn sync_and_ack(&mut self, writer: &mut AuditWriter<impl Write>) -> anyhow::Result<()> {
+        if self.unsynced.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.unsynced);
+        match self.storage.sync() {
+            Ok(()) => {
+                self.sync_stats.syncs += 1;
+                self.sync_stats.synced_sends += batch.len() as u64;
+                self.sync_stats.max_batch = self.sync_stats.max_batch.max(batch.len());
+                for reply in batch {
+                    reply.send(writer).context("write to stdout, sendok")?;
+                }
+            }
+            Err(e) => {
+                self.sync_stats.failed_syncs += 1;
+                eprintln!("fsync failed for {} sends: {e:#}", batch.len());
+                for reply in batch {
+                    if let Some(in_reply_to) = reply.body.in_reply_to {
+                        reply_error(
+                            writer,
+                            reply.src,
+                            reply.dst,
+                            in_reply_to,
+                            error_code::CRASH,
+                            format!("fsync failed: {e:#}"),
+                        )?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-impl Node<(), Payload> for KafkaNode {
-    fn from_init(_init_state: (), init: Init) -> anyhow::Result<Self>
+impl Node<KafkaConfig, Payload> for KafkaNode {
+    fn from_init(config: KafkaConfig, init: Init) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        let mut new = Self {
-            id: init.node_id,
+        let storage: Box<dyn Storage> = match config.storage {
+            StorageBackend::File => {
+                let root = storage::node_dir(&init.node_id, "kafka");
+                Box::new(FileStorage::open(root).context("open file storage")?)
+            }
+            StorageBackend::Memory => Box::new(MemStorage::default()),
+        };
+        let mut node = Self {
             msg_id_seq: 1,
-            topics: vec![],
-            next_offsets: HashMap::new(),
-            file_handles: HashMap::new(),
-            index: HashMap::new(),
-            node_ids: init.node_ids,
+            config,
+            storage,
+            unsynced: Vec::new(),
+            sync_stats: SyncStats::default(),
+            hot_keys: HotKeys::default(),
         };
-        if let Ok(res) = Self::build_index(&new.id).context("building index") {
-            (new.index, new.next_offsets) = res;
+        for problem in node.self_check().context("startup self check")? {
+            eprintln!("self check: {problem}");
         }
-
-        Ok(new)
+        Ok(node)
     }
 
     fn step(
         &mut self,
         input: Message<Payload>,
-        writer: &mut std::io::StdoutLock,
+        writer: &mut AuditWriter<impl Write>,
     ) -> anyhow::Result<()> {
         let mut reply = input.to_reply(Some(&mut self.msg_id_seq));
         match reply.body.payload {
             Payload::Send { topic, message } => {
                 log::debug!("send received: key: {}, message: {}", topic, message);
-                let ofs = self.append_message(&topic, message)?;
+                let ofs = self.storage.append(&topic, message)?;
+                self.hot_keys.record(&topic, self.config.hot_share);
                 reply.body.payload = Payload::SendOk { offset: ofs };
-                reply.send(writer).context("write to stdout, sendok")?;
+                match self.config.sync {
+                    SyncMode::Never => reply.send(writer).context("write to stdout, sendok")?,
+                    SyncMode::Group { max_batch } => {
+                        self.unsynced.push(reply);
+                        if self.unsynced.len() >= max_batch {
+                            self.sync_and_ack(writer)?;
+                        }
+                    }
+                }
             }
             Payload::Poll { offsets } => {
                 let mut result = HashMap::new();
                 for (topic, start_offset) in &offsets {
-                    let v = self.read_messages(topic, *start_offset)?;
+                    let v = self.storage.read_from(topic, *start_offset)?;
+                    let v = v.iter().map(|e| (e.offset, e.message)).collect();
                     result.insert(topic.to_string(), v);
                 }
+                if let Some(max) = self.config.max_poll_bytes {
+                    let trimmed = trim_poll(&mut result, max);
+                    if trimmed > 0 {
+                        log::debug!("poll ok trimmed by {} messages", trimmed);
+                    }
+                }
                 for (key, vals) in &result {
                     log::debug!("poll ok: key: {}, vals: {:?}", key, vals);
                 }
@@ -317,8 +373,35 @@ impl Node<(), Payload> for KafkaNode {
                 reply.send(writer).context("write to stdout, pollok")?;
             }
             Payload::CommitOffsets { offsets } => {
+                // reject the whole request if any commit is past what was ever sent.
+                let beyond = offsets.iter().find(|(topic, offset)| {
+                    self.storage
+                        .high_water_mark(topic)
+                        .is_none_or(|hwm| **offset > hwm)
+                });
+                if let Some((topic, offset)) = beyond {
+                    let text = match self.storage.high_water_mark(topic) {
+                        Some(hwm) => format!(
+                            "commit {offset} for {topic} is beyond its high-water mark {hwm}"
+                        ),
+                        None => format!("commit {offset} for {topic}, which has no messages"),
+                    };
+                    log::debug!("rejected commit_offsets: {}", text);
+                    if let Some(in_reply_to) = reply.body.in_reply_to {
+                        reply_error(
+                            writer,
+                            reply.src,
+                            reply.dst,
+                            in_reply_to,
+                            error_code::PRECONDITION_FAILED,
+                            text,
+                        )?;
+                    }
+                    return Ok(());
+                }
                 for (topic, commit_offset) in offsets {
-                    self.commit(&topic, commit_offset)?;
+                    let key = self.commit_key(&topic, &reply.dst);
+                    self.storage.commit(&key, commit_offset)?;
                 }
                 reply.body.payload = Payload::CommitOffsetsOk;
                 reply
@@ -329,7 +412,8 @@ impl Node<(), Payload> for KafkaNode {
                 let mut commits = HashMap::new();
 
                 for top in &keys {
-                    let Some(v) = self.read_commit(&top) else {
+                    let key = self.commit_key(top, &reply.dst);
+                    let Some(v) = self.storage.committed(&key)? else {
                         continue;
                     };
 
@@ -344,23 +428,80 @@ impl Node<(), Payload> for KafkaNode {
         }
         Ok(())
     }
+
+    // polls and offset listings can be retried; sends and commits can't be shed.
+    fn sheddable(&self, message: &Message<Payload>) -> bool {
+        matches!(
+            message.body.payload,
+            Payload::Poll { .. } | Payload::ListCommittedOffsets { .. }
+        )
+    }
+
+    fn idle(&mut self, writer: &mut AuditWriter<impl Write>) -> anyhow::Result<()> {
+        self.sync_and_ack(writer)
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "sync": self.sync_stats,
+            "unsynced": self.unsynced.len(),
+            "hot_keys": self.hot_keys.stats(),
+            "io": self.storage.io_stats(),
+        })
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        let commits: HashMap<String, usize> = self
+            .storage
+            .commits()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        serde_json::json!({
+            "config": format!("{:?}", self.config),
+            "commits": commits,
+        })
+    }
+    /// Checks the stored state: logs against their index, and every committed
+    /// offset against its topic's high-water mark.
+    fn self_check(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut problems = self.storage.verify()?;
+        for (key, offset) in self.storage.commits()? {
+            let topic = match self.config.commit_scope {
+                CommitScope::Global => key.as_str(),
+                CommitScope::PerClient => key.rsplit_once('@').map_or(key.as_str(), |(t, _)| t),
+            };
+            match self.storage.high_water_mark(topic) {
+                Some(hwm) if offset <= hwm => {}
+                hwm => problems.push(format!(
+                    "commit {key} is at {offset}, beyond the high-water mark {hwm:?} of {topic}"
+                )),
+            }
+        }
+        Ok(problems)
+    }
 }
 
+/// File the node appends its debug log to. Unset, info and up go to stderr.
+const LOG_ENV: &str = "KAFKA_LOG";
+
 fn main() -> anyhow::Result<()> {
-    let log_path = "/Users/shubham/code/kafka-test.log";
-    let log_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(log_path)
-        .expect("Unable to open or create log file");
+    let stderr = || WriteLogger::new(LevelFilter::Info, Config::default(), std::io::stderr());
+    let logger: Box<dyn SharedLogger> = match std::env::var_os(LOG_ENV) {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(log_file) => WriteLogger::new(LevelFilter::Debug, Config::default(), log_file),
+            Err(e) => {
+                eprintln!("unable to open log file {path:?} ({e}), logging to stderr");
+                stderr()
+            }
+        },
+        None => stderr(),
+    };
+    CombinedLogger::init(vec![logger]).context("init logging")?;
     log::info!("-----starting the node-------------------");
-    CombinedLogger::init(vec![WriteLogger::new(
-        LevelFilter::Debug,
-        Config::default(),
-        log_file,
-    )])
-    .unwrap();
 
-    main_loop::<(), KafkaNode, Payload>(())?;
+    let config = KafkaConfig::from_env()?;
+    log::info!("starting with {:?}", config);
+    main_loop::<KafkaConfig, KafkaNode, Payload>(config)?;
     Ok(())
 }
@@ -1,49 +1,120 @@
-use ::serde::{Deserialize, Serialize};
 use anyhow::Context;
+use flyio_dist::ids::{IdGenerator, NodeSeq, Random128, Snowflake, Ulid};
+use flyio_dist::rng::Rng;
+use flyio_dist::workloads::unique_ids::Payload;
 use flyio_dist::*;
 
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(tag = "type", rename_all = "snake_case")]
-enum Payload {
-    Generate,
-    GenerateOk { id: String },
+/// Which `IdGenerator` the node uses, from `UNIQUE_IDS_STRATEGY`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum IdStrategy {
+    /// `snowflake` (default): numeric, unique by construction, also across
+    /// restarts.
+    #[default]
+    Snowflake,
+    /// `ulid`: time-sortable strings, unique with high probability.
+    Ulid,
+    /// `node-seq`: `<node>-<n>`, unique only within one run.
+    NodeSeq,
+    /// `random`: 128 random bits, unique with high probability.
+    Random,
+}
+
+impl IdStrategy {
+    fn from_env() -> anyhow::Result<Self> {
+        let Ok(strategy) = std::env::var("UNIQUE_IDS_STRATEGY") else {
+            return Ok(Self::default());
+        };
+        Ok(match strategy.as_str() {
+            "snowflake" => Self::Snowflake,
+            "ulid" => Self::Ulid,
+            "node-seq" => Self::NodeSeq,
+            "random" => Self::Random,
+            other => anyhow::bail!("unknown UNIQUE_IDS_STRATEGY {other:?}"),
+        })
+    }
 }
 
 struct UniqueIdNode {
-    msg_id_seq: usize,
+    msg_id_seq: u64,
     id: String,
+    strategy: IdStrategy,
+    ids: Box<dyn IdGenerator>,
 }
 
-impl Node<(), Payload> for UniqueIdNode {
-    fn from_init(_init_state: (), init: Init) -> anyhow::Result<Self>
+impl Node<IdStrategy, Payload> for UniqueIdNode {
+    fn from_init(strategy: IdStrategy, init: Init) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
+        let ids: Box<dyn IdGenerator> = match strategy {
+            IdStrategy::Snowflake => {
+                let index = init
+                    .node_ids
+                    .iter()
+                    .position(|n| *n == init.node_id)
+                    .context("node isn't in the cluster's node list")?;
+                Box::new(Snowflake::new(index)?)
+            }
+            IdStrategy::Ulid => Box::new(Ulid::new(Rng::for_node(&init.node_id)?)),
+            IdStrategy::NodeSeq => Box::new(NodeSeq::new(&init.node_id)),
+            IdStrategy::Random => Box::new(Random128::new(Rng::for_node(&init.node_id)?)),
+        };
         Ok(Self {
             id: init.node_id,
             msg_id_seq: 1,
+            strategy,
+            ids,
         })
     }
 
     fn step(
         &mut self,
         message: Message<Payload>,
-        writer: &mut std::io::StdoutLock,
+        writer: &mut AuditWriter<impl std::io::Write>,
     ) -> anyhow::Result<()> {
         let mut reply = message.to_reply(Some(&mut self.msg_id_seq));
         match reply.body.payload {
             Payload::Generate => {
-                let unique_id = format!("{}-{}", self.id, self.msg_id_seq);
-                reply.body.payload = Payload::GenerateOk { id: unique_id };
+                let id = match self.ids.generate() {
+                    Ok(id) => id,
+                    Err(e) => {
+                        if let Some(in_reply_to) = reply.body.in_reply_to {
+                            reply_error(
+                                writer,
+                                reply.src,
+                                reply.dst,
+                                in_reply_to,
+                                error_code::TEMPORARILY_UNAVAILABLE,
+                                format!("{e:#}"),
+                            )?;
+                        }
+                        return Ok(());
+                    }
+                };
+                reply.body.payload = Payload::GenerateOk { id };
                 reply.send(writer).context("failed to write to stdout")?;
             }
             Payload::GenerateOk { .. } => {}
         }
         Ok(())
     }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({ "ids": self.ids.stats() })
+    }
+
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "msg_id_seq": self.msg_id_seq,
+            "strategy": format!("{:?}", self.strategy),
+            "ids": self.ids.stats(),
+        })
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<(), UniqueIdNode, Payload>(())?;
+    let strategy = IdStrategy::from_env()?;
+    main_loop::<IdStrategy, UniqueIdNode, Payload>(strategy)?;
     Ok(())
 }
@@ -0,0 +1,79 @@
+//! Building requests from outside a node, for load generators and drivers.
+//! Only built with the `client` feature.
+//!
+//! ```
+//! use flyio_dist::client::Client;
+//! use flyio_dist::workloads::kafka::Payload;
+//!
+//! let mut client = Client::new("c1");
+//! let send = client.request(
+//!     "n1",
+//!     Payload::Send {
+//!         topic: "k1".to_string(),
+//!         message: 7,
+//!     },
+//! );
+//! assert_eq!(send.body.msg_id, Some(1));
+//! let line = client.line(&send).unwrap();
+//! assert!(line.starts_with(r#"{"src":"c1","dest":"n1""#));
+//! ```
+
+use crate::audit::Envelope;
+use crate::{Body, Message};
+use anyhow::Context;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// A client identity handing out msg_ids from 1, the way Maelstrom's
+/// clients do.
+#[derive(Debug, Clone)]
+pub struct Client {
+    id: String,
+    next_msg_id: u64,
+}
+
+impl Client {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            next_msg_id: 1,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// A request from this client to `dst` with the next msg_id.
+    pub fn request<P>(&mut self, dst: impl Into<String>, payload: P) -> Message<P> {
+        let msg_id = self.next_msg_id;
+        self.next_msg_id += 1;
+        Message {
+            src: self.id.clone(),
+            dst: dst.into(),
+            body: Body {
+                msg_id: Some(msg_id),
+                in_reply_to: None,
+                payload,
+            },
+        }
+    }
+
+    /// `msg` as one line of the wire protocol, without the newline.
+    pub fn line<P: Serialize>(&self, msg: &Message<P>) -> anyhow::Result<String> {
+        serde_json::to_string(msg).context("serialize request")
+    }
+
+    /// Parses a line written by a node; `None` if it isn't addressed to this
+    /// client.
+    pub fn parse_reply<P: DeserializeOwned>(
+        &self,
+        line: &str,
+    ) -> anyhow::Result<Option<Message<P>>> {
+        let envelope = Envelope::peek(line.as_bytes()).context("not a message")?;
+        if envelope.dst != self.id {
+            return Ok(None);
+        }
+        serde_json::from_str(line).context("parse reply").map(Some)
+    }
+}
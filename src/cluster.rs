@@ -0,0 +1,235 @@
+//! Several nodes in one process, so cross-node paths can be profiled with a
+//! single perf or flamegraph session: `FLYIO_NODES=n1,n2,n3` makes
+//! `main_loop` run them all. Each node runs `run_node` on its own thread and
+//! is initialized with the whole list. A line a node writes to another node
+//! of the process goes straight to that node's input. Everything else goes to
+//! stdout. Lines read from stdin are routed to the node named in their `dest`.
+
+use crate::audit::Envelope;
+use crate::{Body, Init, InitPayload, Message, Node, run_node};
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Debug;
+use std::io::{BufRead, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Comma-separated ids of the nodes to run in this process.
+pub const NODES_ENV: &str = "FLYIO_NODES";
+/// Sender of the init messages `run_cluster` hands its nodes. Their init_oks
+/// are dropped.
+pub const INIT_SRC: &str = "cluster";
+/// Once the input has ended, the nodes are shut down when no line has gone
+/// between them for this long, or after `MAX_DRAIN` if they keep talking.
+/// Lines they send each other after that are dropped.
+const QUIET: Duration = Duration::from_millis(100);
+/// The longest the nodes get to finish talking after the input ends.
+pub const MAX_DRAIN: Duration = Duration::from_secs(1);
+
+// the inputs of the nodes, until shutdown empties it.
+type Routes = Arc<RwLock<HashMap<String, mpsc::Sender<Vec<u8>>>>>;
+
+// a node's input: the lines routed to it, ending when its sender is dropped.
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ChannelReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.pos == self.line.len()
+            && let Ok(line) = self.rx.recv()
+        {
+            self.line = line;
+            self.pos = 0;
+        }
+        Ok(&self.line[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+// a node's output: splits what it writes into lines and routes each one.
+struct Router<W> {
+    line: Vec<u8>,
+    nodes: Arc<HashSet<String>>,
+    routes: Routes,
+    routed: Arc<AtomicU64>,
+    output: Arc<Mutex<W>>,
+}
+
+impl<W: Write> Router<W> {
+    fn route(&mut self, line: Vec<u8>) -> std::io::Result<()> {
+        let envelope = Envelope::peek(line.strip_suffix(b"\n").unwrap_or(&line));
+        match envelope {
+            Some(e) if e.kind == "init_ok" && e.dst == INIT_SRC => Ok(()),
+            Some(e) if self.nodes.contains(&e.dst) => {
+                if let Some(tx) = self.routes.read().unwrap().get(&e.dst) {
+                    let _ = tx.send(line);
+                    self.routed.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+            _ => self.output.lock().unwrap().write_all(&line),
+        }
+    }
+}
+
+impl<W: Write> Write for Router<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for chunk in buf.split_inclusive(|b| *b == b'\n') {
+            self.line.extend_from_slice(chunk);
+            if chunk.ends_with(b"\n") {
+                let line = std::mem::take(&mut self.line);
+                self.route(line)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.lock().unwrap().flush()
+    }
+}
+
+/// Runs a node for each of `node_ids`, all of them reading `input` and
+/// writing `output` as described in the module docs, until `input` hits EOF
+/// and the nodes have gone quiet. Fails if any node does.
+pub fn run_cluster<S, N, P>(
+    init_state: S,
+    node_ids: &[String],
+    input: impl BufRead,
+    output: impl Write + Send,
+) -> anyhow::Result<()>
+where
+    N: Node<S, P>,
+    S: Clone + Send,
+    P: DeserializeOwned + Send + 'static + Debug,
+{
+    anyhow::ensure!(!node_ids.is_empty(), "no nodes to run");
+    let output = Arc::new(Mutex::new(output));
+    let routed = Arc::new(AtomicU64::new(0));
+    let mut senders = HashMap::new();
+    let mut readers = Vec::new();
+    for id in node_ids {
+        let (tx, rx) = mpsc::channel();
+        let init = Message {
+            src: INIT_SRC.to_string(),
+            dst: id.clone(),
+            body: Body {
+                msg_id: Some(0),
+                in_reply_to: None,
+                payload: InitPayload::Init(Init {
+                    node_id: id.clone(),
+                    node_ids: node_ids.to_vec(),
+                }),
+            },
+        };
+        let mut line = serde_json::to_vec(&init).context("serialize init")?;
+        line.push(b'\n');
+        tx.send(line).expect("receiver is alive");
+        anyhow::ensure!(
+            senders.insert(id.clone(), tx).is_none(),
+            "node {id} listed twice"
+        );
+        readers.push((
+            id.clone(),
+            ChannelReader {
+                rx,
+                line: Vec::new(),
+                pos: 0,
+            },
+        ));
+    }
+    let nodes: Arc<HashSet<String>> = Arc::new(senders.keys().cloned().collect());
+    let routes: Routes = Arc::new(RwLock::new(senders));
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = readers
+            .into_iter()
+            .map(|(id, reader)| {
+                let router = Router {
+                    line: Vec::new(),
+                    nodes: nodes.clone(),
+                    routes: routes.clone(),
+                    routed: routed.clone(),
+                    output: output.clone(),
+                };
+                let state = init_state.clone();
+                let handle = thread::Builder::new()
+                    .name(id.clone())
+                    .spawn_scoped(scope, move || run_node::<S, N, P>(state, reader, router))
+                    .expect("spawn node thread");
+                (id, handle)
+            })
+            .collect();
+
+        // an input error still shuts the nodes down before it's returned.
+        let mut result = Ok(());
+        let mut input = input;
+        loop {
+            let mut line = Vec::new();
+            match input.read_until(b'\n', &mut line).context("read input") {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            let dst = Envelope::peek(line.strip_suffix(b"\n").unwrap_or(&line)).map(|e| e.dst);
+            match dst
+                .as_ref()
+                .and_then(|dst| routes.read().unwrap().get(dst).cloned())
+            {
+                Some(tx) => {
+                    let _ = tx.send(line);
+                }
+                None => eprintln!("no node in this process for {dst:?}, dropping input"),
+            }
+        }
+
+        let draining = Instant::now();
+        let mut last = routed.load(Ordering::Relaxed);
+        loop {
+            thread::sleep(QUIET.min(MAX_DRAIN.saturating_sub(draining.elapsed())));
+            let now = routed.load(Ordering::Relaxed);
+            if now == last {
+                break;
+            }
+            if draining.elapsed() >= MAX_DRAIN {
+                eprintln!("nodes still talking {MAX_DRAIN:?} after the input ended, closing");
+                break;
+            }
+            last = now;
+        }
+        routes.write().unwrap().clear();
+
+        for (id, handle) in handles {
+            let res = handle.join().expect("node thread panicked");
+            if result.is_ok() {
+                result = res.with_context(|| format!("node {id}"));
+            }
+        }
+        result
+    })
+}
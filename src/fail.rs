@@ -0,0 +1,86 @@
+//! Named failpoints at the places where a crash or an I/O error matters, so
+//! tests can hit those windows deterministically. They are compiled in only
+//! with the `failpoints` feature; without it `fail_point!` expands to nothing.
+//!
+//! Failpoints are armed through `FLYIO_FAILPOINTS`, a `;`-separated list of
+//! `name=action` or `name=action@n`, where `action` is `panic`, `error` or
+//! `exit` and `@n` fires only on the n-th time (from 1) the point is reached.
+//! For example `storage.append=exit@3` kills the node right after its third
+//! append hits the log, before the reply goes out.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+pub const FAILPOINTS_ENV: &str = "FLYIO_FAILPOINTS";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Panic,
+    Error,
+    Exit,
+}
+
+struct Armed {
+    action: Action,
+    // fire only on this hit; every hit if `None`.
+    on_hit: Option<usize>,
+    hits: usize,
+}
+
+fn registry() -> &'static Mutex<HashMap<String, Armed>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Armed>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let spec = std::env::var(FAILPOINTS_ENV).unwrap_or_default();
+        let armed = parse(&spec).unwrap_or_else(|e| panic!("bad {FAILPOINTS_ENV}: {e}"));
+        Mutex::new(armed)
+    })
+}
+
+fn parse(spec: &str) -> anyhow::Result<HashMap<String, Armed>> {
+    let mut armed = HashMap::new();
+    for entry in spec.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let (name, rest) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("{entry:?} is not name=action"))?;
+        let (action, on_hit) = match rest.split_once('@') {
+            Some((action, n)) => (action, Some(n.parse()?)),
+            None => (rest, None),
+        };
+        let action = match action {
+            "panic" => Action::Panic,
+            "error" => Action::Error,
+            "exit" => Action::Exit,
+            other => anyhow::bail!("unknown failpoint action {other:?}"),
+        };
+        armed.insert(
+            name.to_string(),
+            Armed {
+                action,
+                on_hit,
+                hits: 0,
+            },
+        );
+    }
+    Ok(armed)
+}
+
+/// Evaluates the failpoint `name`; used by `fail_point!`.
+pub fn eval(name: &str) -> anyhow::Result<()> {
+    let action = {
+        let mut registry = registry().lock().unwrap();
+        let Some(armed) = registry.get_mut(name) else {
+            return Ok(());
+        };
+        armed.hits += 1;
+        if armed.on_hit.is_some_and(|n| n != armed.hits) {
+            return Ok(());
+        }
+        armed.action
+    };
+    eprintln!("failpoint {name}: {action:?}");
+    match action {
+        Action::Panic => panic!("failpoint {name}"),
+        Action::Error => anyhow::bail!("failpoint {name}"),
+        Action::Exit => std::process::exit(1),
+    }
+}
@@ -0,0 +1,270 @@
+//! Coordination-free unique IDs behind one `IdGenerator` trait. `Snowflake`,
+//! a 64-bit id of milliseconds since `EPOCH_MS`, the node's index in the
+//! cluster and a per-millisecond sequence, is the default; `Ulid`,
+//! `NodeSeq` and `Random128` trade its guarantees for other shapes.
+
+use crate::rng::Rng;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Start of the ID timestamp range (2023-11-14T22:13:20Z), in ms since the
+/// unix epoch.
+pub const EPOCH_MS: u64 = 1_700_000_000_000;
+pub const NODE_BITS: u32 = 10;
+pub const SEQ_BITS: u32 = 12;
+/// Largest node index an ID can hold.
+pub const MAX_NODE: u64 = (1 << NODE_BITS) - 1;
+const MAX_SEQ: u64 = (1 << SEQ_BITS) - 1;
+/// How far the clock may fall behind the last issued timestamp before
+/// `next_id` gives up instead of waiting for it.
+pub const DEFAULT_MAX_BACKWARD_MS: u64 = 1000;
+
+/// Milliseconds since the unix epoch, from the system clock.
+pub fn system_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// An id as it goes on the wire: a number or a string, depending on the
+/// generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Id {
+    Num(u64),
+    Text(String),
+}
+
+/// Something that hands out ids no other node's generator will.
+pub trait IdGenerator {
+    fn generate(&mut self) -> anyhow::Result<Id>;
+
+    /// Generator-specific counters for `admin_stats` and `debug_state`.
+    fn stats(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+}
+
+/// How often a `Snowflake` had to deal with its clock going backwards.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SkewStats {
+    /// ids issued while the clock read earlier than the last issued timestamp.
+    pub behind: u64,
+    /// times the sequence ran out and the generator waited for the clock.
+    pub stalls: u64,
+    /// ids refused because the clock was too far behind, or behind at all
+    /// when the sequence ran out.
+    pub rejected: u64,
+}
+
+/// Generates IDs for one node.
+///
+/// Two generators with different node indexes never collide. For one node
+/// index, IDs stay unique across restarts as long as its clock doesn't go
+/// backwards: a generator only issues timestamps after the millisecond it
+/// was created in, so a restarted node can't reuse the sequence numbers of
+/// the previous incarnation within a shared millisecond.
+///
+/// Within one generator timestamps never go back. If the clock does, IDs keep
+/// using the last issued timestamp's remaining sequence numbers, up to the
+/// allowed bound behind; once those run out, `next_id` fails until the clock
+/// catches up rather than waiting for it.
+pub struct Snowflake {
+    node: u64,
+    last_ms: u64,
+    seq: u64,
+    max_backward_ms: u64,
+    skew: SkewStats,
+    clock: Box<dyn FnMut() -> u64>,
+    sleep: Box<dyn FnMut(Duration)>,
+}
+
+impl Snowflake {
+    /// Generator for the node at `node` in the cluster's node list, on the
+    /// system clock.
+    pub fn new(node: usize) -> anyhow::Result<Self> {
+        Self::with_clock(node, system_ms, thread::sleep)
+    }
+
+    /// Like `new` with `clock` returning ms since the unix epoch and `sleep`
+    /// waiting for it to move on; meant for simulations.
+    pub fn with_clock(
+        node: usize,
+        mut clock: impl FnMut() -> u64 + 'static,
+        sleep: impl FnMut(Duration) + 'static,
+    ) -> anyhow::Result<Self> {
+        let node = node as u64;
+        anyhow::ensure!(node <= MAX_NODE, "node index {node} doesn't fit in an id");
+        let started = clock();
+        anyhow::ensure!(
+            started >= EPOCH_MS,
+            "clock reads {started}, before the id epoch"
+        );
+        let mut generator = Self {
+            node,
+            last_ms: started,
+            seq: 0,
+            max_backward_ms: DEFAULT_MAX_BACKWARD_MS,
+            skew: SkewStats::default(),
+            clock: Box::new(clock),
+            sleep: Box::new(sleep),
+        };
+        // everything issued in `started` may belong to a previous incarnation.
+        generator.last_ms = generator.wait_past(started)?;
+        Ok(generator)
+    }
+
+    /// Sets how far behind the last issued timestamp the clock may be before
+    /// `next_id` fails (`DEFAULT_MAX_BACKWARD_MS` by default).
+    pub fn max_backward_ms(mut self, ms: u64) -> Self {
+        self.max_backward_ms = ms;
+        self
+    }
+
+    pub fn skew_stats(&self) -> SkewStats {
+        self.skew
+    }
+
+    pub fn next_id(&mut self) -> anyhow::Result<u64> {
+        let now = (self.clock)();
+        // behind with the last timestamp used up, there is nothing to issue
+        // until the clock catches up.
+        let used_up = now < self.last_ms && self.seq == MAX_SEQ;
+        if now + self.max_backward_ms < self.last_ms || used_up {
+            self.skew.rejected += 1;
+            anyhow::bail!(
+                "clock is {}ms behind the last issued id",
+                self.last_ms - now
+            );
+        }
+        if now < self.last_ms {
+            self.skew.behind += 1;
+        }
+        if now > self.last_ms {
+            self.last_ms = now;
+            self.seq = 0;
+        } else if self.seq < MAX_SEQ {
+            self.seq += 1;
+        } else {
+            self.skew.stalls += 1;
+            self.last_ms = self.wait_past(self.last_ms)?;
+            self.seq = 0;
+        }
+        Ok(((self.last_ms - EPOCH_MS) << (NODE_BITS + SEQ_BITS))
+            | (self.node << SEQ_BITS)
+            | self.seq)
+    }
+
+    // sleeps until the clock is past `ms`, which it must not be behind.
+    fn wait_past(&mut self, ms: u64) -> anyhow::Result<u64> {
+        loop {
+            let now = (self.clock)();
+            if now > ms {
+                return Ok(now);
+            }
+            anyhow::ensure!(
+                now == ms,
+                "clock is {}ms behind the last issued id",
+                ms - now
+            );
+            (self.sleep)(Duration::from_millis(ms + 1 - now));
+        }
+    }
+}
+
+impl IdGenerator for Snowflake {
+    fn generate(&mut self) -> anyhow::Result<Id> {
+        self.next_id().map(Id::Num)
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({ "clock_skew": self.skew })
+    }
+}
+
+/// ULIDs: 48 bits of milliseconds since the unix epoch and 80 random bits,
+/// as 26 Crockford base32 characters, so they sort by time. Unique with high
+/// probability rather than by construction.
+pub struct Ulid {
+    rng: Rng,
+    clock: Box<dyn FnMut() -> u64>,
+}
+
+impl Ulid {
+    /// `rng` should come from `Rng::for_node`.
+    pub fn new(rng: Rng) -> Self {
+        Self::with_clock(rng, system_ms)
+    }
+
+    /// Like `new` with a given randomness and clock; meant for tests.
+    pub fn with_clock(rng: Rng, clock: impl FnMut() -> u64 + 'static) -> Self {
+        Self {
+            rng,
+            clock: Box::new(clock),
+        }
+    }
+}
+
+impl IdGenerator for Ulid {
+    fn generate(&mut self) -> anyhow::Result<Id> {
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+        let ms = u128::from((self.clock)() & ((1 << 48) - 1));
+        let random = (u128::from(self.rng.next_u64()) << 16
+            | u128::from(self.rng.next_u64() >> 48))
+            & ((1 << 80) - 1);
+        let value = ms << 80 | random;
+        let text = (0..26)
+            .map(|i| ALPHABET[((value >> (125 - 5 * i)) & 31) as usize] as char)
+            .collect();
+        Ok(Id::Text(text))
+    }
+}
+
+/// `<node>-<n>` with a per-node counter. The simplest scheme, but only unique
+/// within one run: a restarted node starts counting again.
+pub struct NodeSeq {
+    node_id: String,
+    next: u64,
+}
+
+impl NodeSeq {
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            next: 0,
+        }
+    }
+}
+
+impl IdGenerator for NodeSeq {
+    fn generate(&mut self) -> anyhow::Result<Id> {
+        let id = format!("{}-{}", self.node_id, self.next);
+        self.next += 1;
+        Ok(Id::Text(id))
+    }
+}
+
+/// 128 random bits as 32 hex digits. Unique with high probability; the
+/// generator's state is 64 bits. Seeded by `Rng::for_node`, so a run that
+/// pins `FLYIO_SEED` repeats the ids of an earlier run with the same seed.
+pub struct Random128 {
+    rng: Rng,
+}
+
+impl Random128 {
+    pub fn new(rng: Rng) -> Self {
+        Self { rng }
+    }
+}
+
+impl IdGenerator for Random128 {
+    fn generate(&mut self) -> anyhow::Result<Id> {
+        Ok(Id::Text(format!(
+            "{:016x}{:016x}",
+            self.rng.next_u64(),
+            self.rng.next_u64()
+        )))
+    }
+}
@@ -1,136 +1,722 @@
+pub mod admin;
+pub mod audit;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod cluster;
+#[cfg(feature = "failpoints")]
+pub mod fail;
+pub mod ids;
+pub mod protocol;
+pub mod rng;
+pub mod storage;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod workloads;
+
+use admin::{ADMIN_PREFIX, AdminPayload, Stats};
 use anyhow::Context;
+pub use audit::AuditWriter;
+use audit::Envelope;
+pub use protocol::*;
 use serde::de::DeserializeOwned;
-use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::{
-    io::{BufRead, StdoutLock, Write},
-    sync::mpsc,
+    io::{BufRead, BufReader, Write},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc, Mutex, Weak,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Message<Payload> {
-    pub src: String,
-    #[serde(rename = "dest")]
-    pub dst: String,
-    pub body: Body<Payload>,
-}
-
-impl<Payload: Debug> Message<Payload> {
-    pub fn to_reply(self, msg_id: Option<&mut usize>) -> Self {
-        Self {
-            src: self.dst,
-            dst: self.src,
-            body: Body {
-                msg_id: msg_id.map(|m| {
-                    let mid = *m;
-                    *m += 1;
-                    mid
-                }),
-                in_reply_to: self.body.msg_id,
-                payload: self.body.payload,
-            },
-        }
-    }
-    pub fn send(&self, writer: &mut impl Write) -> anyhow::Result<()>
+/// Evaluates the named failpoint (see `fail`) inside a function returning
+/// `anyhow::Result`; expands to nothing without the `failpoints` feature.
+#[cfg(feature = "failpoints")]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {
+        $crate::fail::eval($name)?;
+    };
+}
+
+#[cfg(not(feature = "failpoints"))]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {};
+}
+
+/// How long a single `step` may run before the watchdog reports it as stuck.
+const WATCHDOG_THRESHOLD: Duration = Duration::from_secs(1);
+/// How much of an undeserializable input line gets echoed to stderr.
+const MALFORMED_ECHO_LIMIT: usize = 512;
+/// Overrides `DEFAULT_LATENCY_BUDGET`, in milliseconds.
+pub const LATENCY_BUDGET_ENV: &str = "FLYIO_LATENCY_BUDGET_MS";
+/// Requests taking longer than this from receipt to the end of their step
+/// are logged as slow.
+const DEFAULT_LATENCY_BUDGET: Duration = Duration::from_millis(100);
+/// Requests that waited longer than this many milliseconds in the input queue
+/// are shed if the node allows it (see `Node::sheddable`); unset, nothing is.
+pub const SHED_QUEUE_ENV: &str = "FLYIO_SHED_QUEUE_MS";
+/// Directory the end-of-run summary is written to, as `<node>.json`; unset,
+/// it goes to stderr.
+pub const SUMMARY_DIR_ENV: &str = "FLYIO_SUMMARY_DIR";
+
+pub trait Node<S, Payload> {
+    fn from_init(init_state: S, init: Init) -> anyhow::Result<Self>
     where
-        Payload: Serialize,
-    {
-        serde_json::to_writer(&mut *writer, &self).context("serialize response to message")?;
-        writer.write_all(b"\n").context("write new line")?;
+        Self: Sized;
+    fn step(
+        &mut self,
+        message: Message<Payload>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()>;
+
+    /// The node's state as JSON, returned by `admin_dump_state`.
+    fn debug_state(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Replaces the node's state with `state`, as returned by an earlier
+    /// `debug_state`, for `admin_load_state`; `Ok(false)` means the node
+    /// can't be restored this way.
+    fn load_state(&mut self, state: &serde_json::Value) -> anyhow::Result<bool> {
+        let _ = state;
+        Ok(false)
+    }
+
+    /// Workload-specific counters, reported under `node` by `admin_stats`.
+    fn stats(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Changes a runtime setting for `admin_set_config`; `Ok(false)` means
+    /// the node has no setting called `key`.
+    fn set_config(&mut self, key: &str, value: &serde_json::Value) -> anyhow::Result<bool> {
+        let _ = (key, value);
+        Ok(false)
+    }
+
+    /// Handles an `error` body, typically the answer to one of the node's own
+    /// requests (to `lin-kv`, `seq-kv` or a peer). These never reach `step`,
+    /// since workload payloads don't model them; by default they're logged.
+    fn on_error(
+        &mut self,
+        error: Message<ErrorPayload>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        let _ = writer;
+        eprintln!(
+            "unhandled error from {} (in reply to {:?}): {}",
+            error.src, error.body.in_reply_to, error.body.payload
+        );
+        Ok(())
+    }
+
+    /// Called when no input is waiting, before blocking for more, and once
+    /// more when the input ends: the place to finish work batched across
+    /// steps, such as replies held until a group commit.
+    fn idle(&mut self, writer: &mut AuditWriter<impl Write>) -> anyhow::Result<()> {
+        let _ = writer;
         Ok(())
     }
+
+    /// Whether `message` may be answered with temporarily-unavailable instead
+    /// of handled when it has queued past `SHED_QUEUE_ENV`. Reads can be
+    /// retried by the client; writes the node has to acknowledge should not
+    /// be shed.
+    fn sheddable(&self, message: &Message<Payload>) -> bool {
+        let _ = message;
+        false
+    }
+
+    /// Checks the node's state for inconsistencies, returned by
+    /// `admin_self_check`; one entry per problem found.
+    fn self_check(&mut self) -> anyhow::Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+/// One input line as the reader thread hands it to the main loop.
+#[derive(Debug)]
+pub enum Input<P> {
+    Init(Message<InitPayload>),
+    /// the message, its envelope as it appeared on the wire, and when it was
+    /// read.
+    Message(Message<P>, Envelope, Instant),
+    /// an `admin_*` message and its type.
+    Admin(Message<AdminPayload>, String),
+    /// an `error` body, whatever the workload's payload type.
+    Error(Message<ErrorPayload>, Envelope),
+    /// anything else, invalid UTF-8 included; `line` is lossily decoded.
+    Malformed {
+        line: String,
+        error: serde_json::Error,
+    },
+}
+
+/// Frames one input line, without its newline, by the type in its body.
+pub fn frame_line<P: DeserializeOwned>(line: &[u8]) -> Input<P> {
+    let malformed = |error| Input::Malformed {
+        line: String::from_utf8_lossy(line).into_owned(),
+        error,
+    };
+    // anything that parses as a message has a readable envelope.
+    let envelope = Envelope::peek(line);
+    let kind = envelope
+        .as_ref()
+        .map(|e| e.kind.clone())
+        .unwrap_or_default();
+    if kind == "init" {
+        match serde_json::from_slice::<Message<InitPayload>>(line) {
+            Ok(msg) => Input::Init(msg),
+            Err(error) => malformed(error),
+        }
+    } else if kind == "error" {
+        match serde_json::from_slice::<Message<ErrorPayload>>(line) {
+            Ok(msg) => Input::Error(msg, envelope.expect("parsed, so peekable")),
+            Err(error) => malformed(error),
+        }
+    } else if kind.starts_with(ADMIN_PREFIX) {
+        match serde_json::from_slice::<Message<AdminPayload>>(line) {
+            Ok(msg) => Input::Admin(msg, kind),
+            Err(error) => malformed(error),
+        }
+    } else {
+        match serde_json::from_slice::<Message<P>>(line) {
+            Ok(msg) => Input::Message(msg, envelope.expect("parsed, so peekable"), Instant::now()),
+            Err(error) => malformed(error),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct Body<Payload> {
-    pub msg_id: Option<usize>,
-    pub in_reply_to: Option<usize>,
+/// Why `run_node` couldn't get through the init handshake.
+#[derive(Debug, PartialEq)]
+pub enum InitError {
+    /// the input ended before an init message arrived.
+    NoInit,
+    /// an init message named a different node than the one already running.
+    Conflicting {
+        node_id: String,
+        init_node_id: String,
+    },
+}
 
-    #[serde(flatten)]
-    pub payload: Payload,
+impl std::fmt::Display for InitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InitError::NoInit => write!(f, "input ended before an init message"),
+            InitError::Conflicting {
+                node_id,
+                init_node_id,
+            } => write!(f, "node {node_id} got an init message for {init_node_id}"),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-enum InitPayload {
-    Init(Init),
-    InitOk,
+impl std::error::Error for InitError {}
+
+fn send_init_ok(
+    mut reply: Message<InitPayload>,
+    writer: &mut AuditWriter<impl Write>,
+) -> anyhow::Result<()> {
+    reply.body.msg_id = Some(0);
+    reply.send(writer).context("error writing response to init")
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type")]
-#[serde(rename_all = "snake_case")]
-pub struct Init {
-    pub node_id: String,
-    pub node_ids: Vec<String>,
+// inputs the reader has queued that the main loop hasn't taken yet, and the
+// most there have been at once.
+#[derive(Default)]
+struct QueueDepth {
+    now: AtomicU64,
+    max: AtomicU64,
 }
 
-pub trait Node<S, Payload> {
-    fn from_init(init_state: S, init: Init) -> anyhow::Result<Self>
-    where
-        Self: Sized;
-    fn step(&mut self, message: Message<Payload>, writer: &mut StdoutLock) -> anyhow::Result<()>;
+impl QueueDepth {
+    fn queued(&self) {
+        let now = self.now.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn taken(&self) {
+        self.now.fetch_sub(1, Ordering::Relaxed);
+    }
 }
 
+// reads `input` line by line on its own thread until EOF, framing each line
+// as an `Input`. The thread owns the only sender, so the receiver's iterator
+// ends when the input does.
+fn spawn_reader<P>(
+    input: impl BufRead + Send + 'static,
+    depth: Arc<QueueDepth>,
+) -> (mpsc::Receiver<Input<P>>, thread::JoinHandle<()>)
+where
+    P: DeserializeOwned + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    let mut input = input;
+    let jh = thread::spawn(move || {
+        // lines are read as bytes: invalid UTF-8 is malformed input, not a
+        // reason to stop.
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match input.read_until(b'\n', &mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!("error reading input, treating it as the end: {e}");
+                    break;
+                }
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let input = frame_line::<P>(line);
+            depth.queued();
+            if let Err(e) = tx.send(input) {
+                eprintln!("error sending input to tx: {e:?}");
+            }
+        }
+    });
+    (rx, jh)
+}
+
+// logs an input line that didn't match the workload's payload and, if the
+// envelope is readable and the sender expects a reply, answers with a
+// malformed-request error.
+fn report_malformed(
+    writer: &mut AuditWriter<impl Write>,
+    line: &str,
+    error: &serde_json::Error,
+) -> anyhow::Result<()> {
+    let echoed = match line.char_indices().nth(MALFORMED_ECHO_LIMIT) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    };
+    eprintln!("malformed input: {error}\n  input: {echoed}");
+
+    let Ok(envelope) = serde_json::from_str::<Message<serde_json::Value>>(line) else {
+        return Ok(());
+    };
+    let Some(msg_id) = envelope.body.msg_id else {
+        return Ok(());
+    };
+    let kind = envelope
+        .body
+        .payload
+        .get("type")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or("<missing type>");
+    reply_error(
+        writer,
+        envelope.dst,
+        envelope.src,
+        msg_id,
+        error_code::MALFORMED_REQUEST,
+        format!("malformed {kind} message: {error}"),
+    )
+}
+
+// brings the counters kept outside `stats` up to date.
+fn refresh_stats<W: Write>(
+    stats: &mut Stats,
+    started: Instant,
+    depth: &QueueDepth,
+    output: &AuditWriter<W>,
+) {
+    stats.uptime_ms = started.elapsed().as_millis() as u64;
+    stats.max_queue_depth = depth.max.load(Ordering::Relaxed);
+    stats.sent = output.sent().clone();
+    stats.sent_sizes = output.sent_sizes().clone();
+    stats.node_msgs_sent = output.sent_to_nodes();
+    stats.msgs_per_op = match stats.client_ops {
+        0 => 0.0,
+        ops => stats.node_msgs_sent as f64 / ops as f64,
+    };
+}
+
+// writes the end-of-run summary where `SUMMARY_DIR_ENV` says.
+fn write_summary(node_id: &str, stats: &Stats) -> anyhow::Result<()> {
+    let summary = serde_json::json!({ "node_id": node_id, "stats": stats });
+    match std::env::var(SUMMARY_DIR_ENV) {
+        Ok(dir) => {
+            std::fs::create_dir_all(&dir).context("create summary dir")?;
+            let path = std::path::Path::new(&dir).join(format!("{node_id}.json"));
+            std::fs::write(&path, serde_json::to_vec(&summary)?)
+                .with_context(|| format!("write summary to {}", path.display()))
+        }
+        Err(_) => {
+            eprintln!("summary: {summary}");
+            Ok(())
+        }
+    }
+}
+
+fn latency_budget() -> anyhow::Result<Duration> {
+    match std::env::var(LATENCY_BUDGET_ENV) {
+        Ok(ms) => {
+            Ok(Duration::from_millis(ms.parse().with_context(|| {
+                format!("invalid {LATENCY_BUDGET_ENV} {ms:?}")
+            })?))
+        }
+        Err(_) => Ok(DEFAULT_LATENCY_BUDGET),
+    }
+}
+
+fn shed_queue_limit() -> anyhow::Result<Option<Duration>> {
+    match std::env::var(SHED_QUEUE_ENV) {
+        Ok(ms) => Ok(Some(Duration::from_millis(
+            ms.parse()
+                .with_context(|| format!("invalid {SHED_QUEUE_ENV} {ms:?}"))?,
+        ))),
+        Err(_) => Ok(None),
+    }
+}
+
+// the message currently being handled by `step`, shared with the watchdog thread.
+struct InFlight {
+    started: Instant,
+    what: String,
+    reported: bool,
+}
+
+type InFlightSlot = Mutex<Option<InFlight>>;
+
+fn spawn_watchdog(slot: Weak<InFlightSlot>) {
+    thread::spawn(move || {
+        // exits once the main loop drops the slot.
+        while let Some(slot) = slot.upgrade() {
+            if let Some(inflight) = slot.lock().unwrap().as_mut()
+                && !inflight.reported
+                && inflight.started.elapsed() > WATCHDOG_THRESHOLD
+            {
+                eprintln!(
+                    "[watchdog] step stuck: {} has been running for {:?}",
+                    inflight.what,
+                    inflight.started.elapsed()
+                );
+                inflight.reported = true;
+            }
+            drop(slot);
+            thread::sleep(WATCHDOG_THRESHOLD / 4);
+        }
+    });
+}
+
+// answers an `admin_*` message on the node's behalf.
+fn handle_admin<S, P, N: Node<S, P>, W: Write>(
+    node: &mut N,
+    msg: Message<AdminPayload>,
+    stats: &Stats,
+    output: &mut AuditWriter<W>,
+) -> anyhow::Result<()> {
+    let mut reply = msg.to_reply(None);
+    reply.body.payload = match reply.body.payload {
+        AdminPayload::AdminStats => AdminPayload::AdminStatsOk {
+            stats: Stats {
+                node: node.stats(),
+                ..stats.clone()
+            },
+        },
+        AdminPayload::AdminDumpState => AdminPayload::AdminDumpStateOk {
+            state: node.debug_state(),
+            audit: output.records().cloned().collect(),
+        },
+        AdminPayload::AdminLoadState { state } => {
+            let error = match node.load_state(&state) {
+                Ok(true) => None,
+                Ok(false) => Some((
+                    error_code::NOT_SUPPORTED,
+                    "this node can't load a state".to_string(),
+                )),
+                Err(e) => Some((error_code::MALFORMED_REQUEST, format!("bad state: {e:#}"))),
+            };
+            if let Some((code, text)) = error {
+                if let Some(in_reply_to) = reply.body.in_reply_to {
+                    reply_error(output, reply.src, reply.dst, in_reply_to, code, text)?;
+                }
+                return Ok(());
+            }
+            AdminPayload::AdminLoadStateOk
+        }
+        AdminPayload::AdminSetConfig { key, value } => {
+            let error = match node.set_config(&key, &value) {
+                Ok(true) => None,
+                Ok(false) => Some((error_code::NOT_SUPPORTED, format!("no setting {key:?}"))),
+                Err(e) => Some((
+                    error_code::MALFORMED_REQUEST,
+                    format!("bad value for {key}: {e:#}"),
+                )),
+            };
+            if let Some((code, text)) = error {
+                if let Some(in_reply_to) = reply.body.in_reply_to {
+                    reply_error(output, reply.src, reply.dst, in_reply_to, code, text)?;
+                }
+                return Ok(());
+            }
+            AdminPayload::AdminSetConfigOk
+        }
+        AdminPayload::AdminSelfCheck => AdminPayload::AdminSelfCheckOk {
+            problems: node.self_check().context("self check")?,
+        },
+        // replies to admin messages aren't addressed to nodes.
+        _ => return Ok(()),
+    };
+    reply.send(output).context("send admin reply")
+}
+
+/// Runs a node over stdin/stdout, the way Maelstrom drives it, or several
+/// if `cluster::NODES_ENV` is set (see `cluster`).
 pub fn main_loop<S, N, P>(init_state: S) -> anyhow::Result<()>
 where
-    N: Node<S, P> + Send,
+    N: Node<S, P>,
+    S: Clone + Send,
     P: DeserializeOwned + Send + 'static + Debug,
 {
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
-
-    let init_msg: Message<InitPayload> = serde_json::from_str(
-        &stdin
-            .next()
-            .expect("no init message received")
-            .context("failed to read init message from stdin")?,
+    if let Ok(nodes) = std::env::var(cluster::NODES_ENV) {
+        let node_ids: Vec<String> = nodes
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect();
+        return cluster::run_cluster::<S, N, P>(
+            init_state,
+            &node_ids,
+            BufReader::new(std::io::stdin()),
+            std::io::stdout(),
+        );
+    }
+    run_node::<S, N, P>(
+        init_state,
+        BufReader::new(std::io::stdin()),
+        std::io::stdout().lock(),
     )
-    .context("init message cound not be deserialized")?;
+}
+
+/// Runs a node over arbitrary streams: performs the init handshake, then feeds
+/// every following line of `input` to the node until `input` hits EOF.
+pub fn run_node<S, N, P>(
+    init_state: S,
+    input: impl BufRead + Send + 'static,
+    output: impl Write,
+) -> anyhow::Result<()>
+where
+    N: Node<S, P>,
+    P: DeserializeOwned + Send + 'static + Debug,
+{
+    let budget = latency_budget()?;
+    let shed_after = shed_queue_limit()?;
+    let mut output = AuditWriter::new(output);
+    let depth = Arc::new(QueueDepth::default());
+    let (rx, jh) = spawn_reader::<P>(input, depth.clone());
 
-    let InitPayload::Init(init) = init_msg.body.payload else {
-        panic!("first message should be an init message");
+    // anything that shows up before init is handled right after it.
+    let mut pending = Vec::new();
+    let init_msg = loop {
+        let input = rx.recv().map_err(|_| InitError::NoInit)?;
+        depth.taken();
+        match input {
+            Input::Init(msg) => break msg,
+            other => pending.push(other),
+        }
     };
+    let mut init_reply = init_msg.to_reply(None);
+    let InitPayload::Init(init) =
+        std::mem::replace(&mut init_reply.body.payload, InitPayload::InitOk)
+    else {
+        unreachable!("the reader only frames init bodies as Input::Init");
+    };
+    let node_id = init.node_id.clone();
+    #[cfg(feature = "trace")]
+    let mut trace = trace::Trace::new(&init.node_id, &init.node_ids);
+    let init_started = Instant::now();
     let mut node: N = Node::from_init(init_state, init).context("node initialization failed")?;
-    let init_reply = Message {
-        src: init_msg.dst,
-        dst: init_msg.src,
-        body: Body {
-            msg_id: Some(0),
-            in_reply_to: init_msg.body.msg_id,
-            payload: InitPayload::InitOk,
-        },
+    let init_time = init_started.elapsed();
+    send_init_ok(init_reply, &mut output)?;
+
+    let inflight: Arc<InFlightSlot> = Arc::new(Mutex::new(None));
+    spawn_watchdog(Arc::downgrade(&inflight));
+
+    let started = Instant::now();
+    let mut stats = Stats {
+        init_ms: init_time.as_millis() as u64,
+        ..Stats::default()
     };
-    serde_json::to_writer(&mut stdout, &init_reply).context("error serializing respose to init")?;
-    stdout
-        .write_all(b"\n")
-        .context("error writing new line to stdout")?;
-    drop(stdin);
-    let (tx, rx) = mpsc::channel();
-    let tx_std = tx.clone();
-    let jh = thread::spawn(move || {
-        let stdin = std::io::stdin().lock();
-        for line in stdin.lines() {
-            let line = line.expect("error reading next line from stdin");
-            // println!("input received: {:?}", line);
-            let input: Message<P> = serde_json::from_str(&line)
-                .context("input could not be deserialized")
-                .unwrap();
-
-            // println!("input received: {:?}", &input);
-            if let Err(e) = tx_std.send(input) {
-                eprintln!("error sending input to tx: {e:?}");
+    let mut pending = pending.into_iter();
+    loop {
+        let input = match pending.next() {
+            Some(input) => input,
+            None => {
+                let input = match rx.try_recv() {
+                    Ok(input) => input,
+                    Err(_) => {
+                        node.idle(&mut output).context("idle")?;
+                        match rx.recv() {
+                            Ok(input) => input,
+                            Err(_) => break,
+                        }
+                    }
+                };
+                depth.taken();
+                input
+            }
+        };
+        let (msg, kind, received) = match input {
+            Input::Init(msg) => {
+                let mut reply = msg.to_reply(None);
+                if let InitPayload::Init(init) =
+                    std::mem::replace(&mut reply.body.payload, InitPayload::InitOk)
+                    && init.node_id != node_id
+                {
+                    return Err(InitError::Conflicting {
+                        node_id,
+                        init_node_id: init.node_id,
+                    }
+                    .into());
+                }
+                // a repeated init for this node is answered the same way again.
+                send_init_ok(reply, &mut output)?;
+                continue;
+            }
+            Input::Message(msg, envelope, received) => {
+                *stats.received.entry(envelope.kind.clone()).or_default() += 1;
+                if msg.src.starts_with('c') {
+                    stats.client_ops += 1;
+                }
+                let kind = envelope.kind.clone();
+                output.begin(envelope);
+                (msg, kind, received)
+            }
+            Input::Admin(msg, kind) => {
+                *stats.received.entry(kind).or_default() += 1;
+                refresh_stats(&mut stats, started, &depth, &output);
+                handle_admin(&mut node, msg, &stats, &mut output)?;
+                continue;
             }
+            Input::Error(msg, envelope) => {
+                *stats.received.entry("error".to_string()).or_default() += 1;
+                output.begin(envelope);
+                node.on_error(msg, &mut output)
+                    .context("handle error body")?;
+                output.end();
+                continue;
+            }
+            Input::Malformed { line, error } => {
+                stats.malformed += 1;
+                report_malformed(&mut output, &line, &error)?;
+                continue;
+            }
+        };
+        if shed_after.is_some_and(|limit| received.elapsed() > limit) && node.sheddable(&msg) {
+            stats.shed += 1;
+            if let Some(msg_id) = msg.body.msg_id {
+                reply_error(
+                    &mut output,
+                    msg.dst,
+                    msg.src,
+                    msg_id,
+                    error_code::TEMPORARILY_UNAVAILABLE,
+                    format!(
+                        "overloaded, {kind} shed after {:?} queued",
+                        received.elapsed()
+                    ),
+                )?;
+            }
+            output.end();
+            continue;
         }
-    });
-
-    for msg in rx {
-        node.step(msg, &mut stdout).unwrap();
+        let step_started = Instant::now();
+        let write_before = output.write_time();
+        *inflight.lock().unwrap() = Some(InFlight {
+            started: step_started,
+            what: format!(
+                "msg_id {:?} from {} to {}",
+                msg.body.msg_id, msg.src, msg.dst
+            ),
+            reported: false,
+        });
+        let (src, dst, msg_id) = (msg.src.clone(), msg.dst.clone(), msg.body.msg_id);
+        // a handler that panics or returns an error fails the one request
+        // instead of the whole node. A failed write still ends the node: the
+        // error reply can't be written either.
+        let failure = match panic::catch_unwind(AssertUnwindSafe(|| node.step(msg, &mut output))) {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => {
+                stats.step_errors += 1;
+                eprintln!("step failed on msg_id {msg_id:?} from {src}: {e:#}");
+                Some(format!("{e:#}"))
+            }
+            Err(cause) => {
+                let text = cause
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| cause.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "handler panicked".to_string());
+                stats.panics += 1;
+                eprintln!("step panicked on msg_id {msg_id:?} from {src}: {text}");
+                Some(text)
+            }
+        };
+        if let Some(text) = failure
+            && let Some(msg_id) = msg_id
+        {
+            reply_error(
+                &mut output,
+                dst,
+                src.clone(),
+                msg_id,
+                error_code::CRASH,
+                text,
+            )?;
+        }
+        #[cfg(feature = "trace")]
+        trace.step(trace::Span {
+            kind: &kind,
+            src: &src,
+            msg_id,
+            trace_id: output.cause().and_then(|c| c.trace_id.as_deref()),
+            received,
+            started: step_started,
+            finished: Instant::now(),
+        });
+        output.end();
+        let total = received.elapsed();
+        if total > budget {
+            stats.slow += 1;
+            let write = output.write_time() - write_before;
+            eprintln!(
+                "[slow] {kind} msg_id {msg_id:?} from {src} took {total:?} \
+                 (queue {:?}, handler {:?}, write {write:?})",
+                step_started - received,
+                step_started.elapsed().saturating_sub(write),
+            );
+        }
+        if let Some(done) = inflight.lock().unwrap().take()
+            && done.reported
+        {
+            eprintln!(
+                "[watchdog] step finished: {} after {:?}",
+                done.what,
+                done.started.elapsed()
+            );
+        }
+    }
+    jh.join()
+        .map_err(|_| anyhow::anyhow!("input reader panicked"))?;
+    output.flush().context("flush output")?;
+    // a lost trace shouldn't cost the audit records and summary too.
+    #[cfg(feature = "trace")]
+    if let Err(e) = trace.write() {
+        eprintln!("write trace: {e:#}");
+    }
+    for record in output.records() {
+        eprintln!("audit: {}", serde_json::to_string(record)?);
     }
-    drop(tx);
-    jh.join().unwrap();
+    refresh_stats(&mut stats, started, &depth, &output);
+    stats.node = node.stats();
+    write_summary(&node_id, &stats)?;
     Ok(())
 }
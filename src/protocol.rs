@@ -0,0 +1,341 @@
+//! The Maelstrom wire format shared by every workload: the message envelope,
+//! the init handshake and error bodies. Each workload's own bodies are in
+//! `workloads`. `tests/wire_format.rs` pins the exact JSON against examples
+//! from the Maelstrom docs.
+
+use crate::audit::{self, AuditWriter, Envelope};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::io::Write;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Message<Payload> {
+    pub src: String,
+    #[serde(rename = "dest")]
+    pub dst: String,
+    pub body: Body<Payload>,
+}
+
+impl<Payload: Debug> Message<Payload> {
+    pub fn to_reply(self, msg_id: Option<&mut u64>) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            body: Body {
+                msg_id: msg_id.map(|m| {
+                    let mid = *m;
+                    *m += 1;
+                    mid
+                }),
+                in_reply_to: self.body.msg_id,
+                payload: self.body.payload,
+            },
+        }
+    }
+    pub fn send(&self, writer: &mut AuditWriter<impl Write>) -> anyhow::Result<()>
+    where
+        Payload: Serialize,
+    {
+        crate::fail_point!("send");
+        let mut line = serde_json::to_vec(self).context("serialize response to message")?;
+        line.push(b'\n');
+        let (kind, trace_id) = audit::body_fields(&self.body);
+        let envelope = Envelope {
+            src: self.src.clone(),
+            dst: self.dst.clone(),
+            kind,
+            msg_id: self.body.msg_id,
+            in_reply_to: self.body.in_reply_to,
+            trace_id,
+        };
+        writer
+            .write_message(envelope, &line)
+            .context("write message")
+    }
+}
+
+// a complete serialized message, newline included.
+fn write_line(
+    writer: &mut AuditWriter<impl Write>,
+    envelope: Envelope,
+    line: &[u8],
+) -> anyhow::Result<()> {
+    crate::fail_point!("send");
+    writer
+        .write_message(envelope, line)
+        .context("write message")
+}
+
+/// Sends `body` from `src` to each of `peers`. The body is serialized once
+/// and only the envelope is written per peer; the lines are the same as
+/// `Message::send` would write. Every peer is attempted; failures are
+/// reported together.
+pub fn send_to_many<'a, P>(
+    writer: &mut AuditWriter<impl Write>,
+    src: &str,
+    peers: impl IntoIterator<Item = &'a str>,
+    body: &Body<P>,
+) -> anyhow::Result<()>
+where
+    P: Serialize,
+{
+    let body_json = serde_json::to_vec(body).context("serialize body")?;
+    let (kind, trace_id) = audit::body_fields(body);
+    let envelope = Envelope {
+        src: src.to_string(),
+        dst: String::new(),
+        kind,
+        msg_id: body.msg_id,
+        in_reply_to: body.in_reply_to,
+        trace_id,
+    };
+    let src = serde_json::to_string(src).context("serialize src")?;
+    let mut line = Vec::with_capacity(body_json.len() + 64);
+    let mut failed = Vec::new();
+    for peer in peers {
+        line.clear();
+        line.extend_from_slice(b"{\"src\":");
+        line.extend_from_slice(src.as_bytes());
+        line.extend_from_slice(b",\"dest\":");
+        serde_json::to_writer(&mut line, peer).context("serialize dest")?;
+        line.extend_from_slice(b",\"body\":");
+        line.extend_from_slice(&body_json);
+        line.extend_from_slice(b"}\n");
+        let envelope = Envelope {
+            dst: peer.to_string(),
+            ..envelope.clone()
+        };
+        if let Err(e) = write_line(writer, envelope, &line) {
+            failed.push(format!("{peer}: {e:#}"));
+        }
+    }
+    send_failures(failed)
+}
+
+/// Like `send_to_many`, but with the body built per peer by `body`, for
+/// messages that differ by destination. Each message is serialized whole.
+pub fn send_to_each<'a, P, F>(
+    writer: &mut AuditWriter<impl Write>,
+    src: &str,
+    peers: impl IntoIterator<Item = &'a str>,
+    mut body: F,
+) -> anyhow::Result<()>
+where
+    P: Serialize + Debug,
+    F: FnMut(&str) -> Body<P>,
+{
+    let mut failed = Vec::new();
+    for peer in peers {
+        let msg = Message {
+            src: src.to_string(),
+            dst: peer.to_string(),
+            body: body(peer),
+        };
+        if let Err(e) = msg.send(writer) {
+            failed.push(format!("{peer}: {e:#}"));
+        }
+    }
+    send_failures(failed)
+}
+
+// one error naming every peer a send failed for.
+fn send_failures(failed: Vec<String>) -> anyhow::Result<()> {
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "send failed for {} peer(s): {}",
+            failed.len(),
+            failed.join("; ")
+        );
+    }
+    Ok(())
+}
+
+/// Ids are read leniently, since not every peer writes them the way our
+/// nodes do: a missing id or `null` is `None`, and an integral float (`3.0`)
+/// or a decimal string (`"3"`) is taken as the number. They are always
+/// written as plain numbers.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Body<Payload> {
+    #[serde(default, deserialize_with = "lenient_id")]
+    pub msg_id: Option<u64>,
+    #[serde(default, deserialize_with = "lenient_id")]
+    pub in_reply_to: Option<u64>,
+
+    #[serde(flatten)]
+    pub payload: Payload,
+}
+
+pub(crate) fn lenient_id<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    struct Visitor;
+    impl serde::de::Visitor<'_> for Visitor {
+        type Value = Option<u64>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a non-negative integer message id")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(Some(v))
+        }
+
+        fn visit_i64<E: serde::de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            u64::try_from(v)
+                .map(Some)
+                .map_err(|_| E::invalid_value(serde::de::Unexpected::Signed(v), &self))
+        }
+
+        fn visit_f64<E: serde::de::Error>(self, v: f64) -> Result<Self::Value, E> {
+            // 2^64 is the first float past u64::MAX.
+            if v.fract() == 0.0 && (0.0..18_446_744_073_709_551_616.0).contains(&v) {
+                Ok(Some(v as u64))
+            } else {
+                Err(E::invalid_value(serde::de::Unexpected::Float(v), &self))
+            }
+        }
+
+        fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            v.parse()
+                .map(Some)
+                .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self))
+        }
+
+        fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+    }
+    deserializer.deserialize_any(Visitor)
+}
+
+/// The handshake Maelstrom opens every node with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum InitPayload {
+    Init(Init),
+    InitOk,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Init {
+    pub node_id: String,
+    pub node_ids: Vec<String>,
+}
+
+/// Maelstrom's standard error body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ErrorPayload {
+    Error { code: usize, text: String },
+}
+
+impl std::fmt::Display for ErrorPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorPayload::Error { code, text } => write!(f, "error {code}: {text}"),
+        }
+    }
+}
+
+impl std::error::Error for ErrorPayload {}
+
+/// Error codes defined by the Maelstrom protocol.
+pub mod error_code {
+    pub const TIMEOUT: usize = 0;
+    pub const NODE_NOT_FOUND: usize = 1;
+    pub const NOT_SUPPORTED: usize = 10;
+    pub const TEMPORARILY_UNAVAILABLE: usize = 11;
+    pub const MALFORMED_REQUEST: usize = 12;
+    pub const CRASH: usize = 13;
+    pub const ABORT: usize = 14;
+    pub const KEY_DOES_NOT_EXIST: usize = 20;
+    pub const KEY_ALREADY_EXISTS: usize = 21;
+    pub const PRECONDITION_FAILED: usize = 22;
+    pub const TXN_CONFLICT: usize = 30;
+}
+
+/// Sends an error reply from `src` to `dst` for the request `in_reply_to`.
+pub fn reply_error(
+    writer: &mut AuditWriter<impl Write>,
+    src: String,
+    dst: String,
+    in_reply_to: u64,
+    code: usize,
+    text: String,
+) -> anyhow::Result<()> {
+    Message {
+        src,
+        dst,
+        body: Body {
+            msg_id: None,
+            in_reply_to: Some(in_reply_to),
+            payload: ErrorPayload::Error { code, text },
+        },
+    }
+    .send(writer)
+    .context("send error reply")
+}
+
+/// Body field naming the client operation a message belongs to. The node a
+/// client request first reaches sets it on whatever it forwards, and
+/// forwards carry it along, so the audit records and traces of every node
+/// can be joined on it.
+pub const TRACE_ID: &str = "trace_id";
+
+/// A payload together with whatever body fields it doesn't know about, so
+/// replies and forwards can carry them along. Use `Message<WithExtra<P>>`
+/// where `Message<P>` would drop them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithExtra<P> {
+    pub payload: P,
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl<P> From<P> for WithExtra<P> {
+    fn from(payload: P) -> Self {
+        Self {
+            payload,
+            extra: serde_json::Map::new(),
+        }
+    }
+}
+
+impl<P: Serialize> Serialize for WithExtra<P> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
+        let serde_json::Value::Object(mut fields) =
+            serde_json::to_value(&self.payload).map_err(S::Error::custom)?
+        else {
+            return Err(S::Error::custom("payload doesn't serialize to an object"));
+        };
+        for (key, value) in &self.extra {
+            if !fields.contains_key(key) {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+        fields.serialize(serializer)
+    }
+}
+
+impl<'de, P: Serialize + serde::de::DeserializeOwned> Deserialize<'de> for WithExtra<P> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        // a second flattened map next to the payload would see every field,
+        // so the extra ones are whatever the payload doesn't write back.
+        let mut extra = serde_json::Map::deserialize(deserializer)?;
+        let payload: P = serde_json::from_value(serde_json::Value::Object(extra.clone()))
+            .map_err(D::Error::custom)?;
+        if let serde_json::Value::Object(known) =
+            serde_json::to_value(&payload).map_err(D::Error::custom)?
+        {
+            extra.retain(|key, _| !known.contains_key(key));
+        }
+        Ok(Self { payload, extra })
+    }
+}
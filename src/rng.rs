@@ -0,0 +1,79 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Environment variable holding the run seed. When it is set every node derives
+/// its stream from it, so repeated runs make the same random choices.
+pub const SEED_ENV: &str = "FLYIO_SEED";
+
+/// Small deterministic PRNG (splitmix64). Not cryptographic; meant for jitter,
+/// peer selection and the like where reproducibility matters more than quality.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn from_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Rng for `node_id`: derived from `FLYIO_SEED` if it is set, otherwise
+    /// from the clock. Nodes sharing a seed still get distinct streams.
+    pub fn for_node(node_id: &str) -> anyhow::Result<Self> {
+        let seed = match std::env::var(SEED_ENV) {
+            Ok(s) => s
+                .parse::<u64>()
+                .map_err(|e| anyhow::anyhow!("invalid {SEED_ENV} {s:?}: {e}"))?,
+            Err(_) => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+        };
+        Ok(Self::from_seed(node_seed(seed, node_id)))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// Uniform value in `0..bound`. `bound` must be non-zero.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        assert!(bound > 0, "Rng::below called with a zero bound");
+        // rejection sampling to avoid modulo bias
+        let zone = u64::MAX - (u64::MAX % bound);
+        loop {
+            let v = self.next_u64();
+            if v < zone {
+                return v % bound;
+            }
+        }
+    }
+
+    /// Picks a random element of `items`, `None` if it is empty.
+    pub fn choose<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        items.get(self.below(items.len() as u64) as usize)
+    }
+
+    /// `base` plus a random amount of up to `percent`% of it, for retry and
+    /// timer jitter. Saturates at `Duration::MAX`.
+    pub fn jitter(&mut self, base: Duration, percent: u32) -> Duration {
+        let unit = self.next_u64() as f64 / u64::MAX as f64;
+        let extra = base.as_secs_f64() * f64::from(percent) / 100.0 * unit;
+        base.saturating_add(Duration::try_from_secs_f64(extra).unwrap_or(Duration::MAX))
+    }
+}
+
+// splitmix64's output function.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Seed for `node_id`'s stream out of a run seed. Splitmix64 over the id's
+/// bytes, so it is the same on every build, unlike std's hashers.
+pub fn node_seed(seed: u64, node_id: &str) -> u64 {
+    node_id.bytes().fold(mix(seed), |h, b| {
+        mix(h.wrapping_add(0x9e37_79b9_7f4a_7c15) ^ u64::from(b))
+    })
+}
@@ -0,0 +1,730 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Root of all persistent state, relative to the working directory.
+pub const DATA_DIR: &str = "data";
+
+/// Most threads `FileStorage::open` indexes logs with.
+const INDEX_THREADS: usize = 8;
+
+/// Directory owned by one workload on one node: `data/<node>/<workload>`.
+pub fn node_dir(node_id: &str, workload: &str) -> PathBuf {
+    Path::new(DATA_DIR)
+        .join(encode_name(node_id))
+        .join(encode_name(workload))
+}
+
+/// Turns an arbitrary name (topic, client, ...) into a single safe path
+/// component: ascii alphanumerics, `-` and `_` are kept, every other byte is
+/// written as `%XX`. That rules out separators, `..` and suffix collisions.
+pub fn encode_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+/// Inverse of `encode_name`, `None` if `encoded` isn't its output.
+pub fn decode_name(encoded: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LogEntry {
+    pub offset: usize,
+    pub message: usize,
+}
+
+/// Disk I/O a backend has done, for tuning what trades writes for latency
+/// (group commit, compaction, index checkpoints).
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct IoStats {
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub fsyncs: u64,
+    /// the same, by `Storage` method; `open` is the indexing done at startup.
+    pub ops: BTreeMap<&'static str, OpIo>,
+}
+
+/// I/O done by the calls of one `Storage` method.
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq)]
+pub struct OpIo {
+    pub calls: u64,
+    pub bytes_written: u64,
+    pub bytes_read: u64,
+    pub fsyncs: u64,
+}
+
+impl IoStats {
+    fn record(&mut self, op: &'static str, io: OpIo) {
+        self.bytes_written += io.bytes_written;
+        self.bytes_read += io.bytes_read;
+        self.fsyncs += io.fsyncs;
+        let total = self.ops.entry(op).or_default();
+        total.calls += 1;
+        total.bytes_written += io.bytes_written;
+        total.bytes_read += io.bytes_read;
+        total.fsyncs += io.fsyncs;
+    }
+}
+
+/// Per-topic append-only logs plus a small committed-offset store. Handlers
+/// only talk to this trait, so the backend can be picked at startup.
+pub trait Storage {
+    /// Appends `message` to `topic`'s log and returns the offset it got.
+    fn append(&mut self, topic: &str, message: usize) -> anyhow::Result<usize>;
+
+    /// Entries of `topic` with an offset of at least `from`, in offset order.
+    fn read_from(&mut self, topic: &str, from: usize) -> anyhow::Result<Vec<LogEntry>>;
+
+    /// Highest offset handed out in `topic`, `None` if nothing was appended.
+    fn high_water_mark(&self, topic: &str) -> Option<usize>;
+
+    /// Stores `offset` as the committed offset under `key`.
+    fn commit(&mut self, key: &str, offset: usize) -> anyhow::Result<()>;
+
+    /// Committed offset stored under `key`, if any.
+    fn committed(&self, key: &str) -> anyhow::Result<Option<usize>>;
+
+    /// Every key in the commit store with its committed offset. Entries that
+    /// can't be read are left out; `verify` reports them.
+    fn commits(&self) -> anyhow::Result<Vec<(String, usize)>>;
+
+    /// Checks that the stored logs and commits are consistent with what the
+    /// backend believes about them; returns a description of each problem
+    /// found.
+    fn verify(&mut self) -> anyhow::Result<Vec<String>>;
+
+    /// Makes every append so far durable, e.g. with an fsync. `append` only
+    /// gets an entry to the OS, so it survives the process but not the
+    /// machine. Backends that don't write to disk have nothing to do.
+    fn sync(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Disk I/O done so far; nothing for backends that don't touch disk.
+    fn io_stats(&self) -> IoStats {
+        IoStats::default()
+    }
+}
+
+#[derive(Debug)]
+struct FileHandle {
+    r: File,
+    w: BufWriter<File>,
+    // logical end of the log, including bytes still sitting in `w`.
+    len: u64,
+    // appended to since the last `sync`.
+    unsynced: bool,
+}
+
+impl FileHandle {
+    // drops whatever a failed append left in `w` or wrote past `len`.
+    fn discard_unflushed(&mut self) -> anyhow::Result<()> {
+        let file = self.w.get_ref().try_clone().context("reopen log")?;
+        // `into_parts` hands back the buffer instead of flushing it on drop.
+        let _ = std::mem::replace(&mut self.w, BufWriter::new(file)).into_parts();
+        self.w
+            .get_ref()
+            .set_len(self.len)
+            .context("truncate failed append")
+    }
+}
+
+/// Files under a root directory (see `node_dir`): each topic's log in
+/// `topics/<topic>/log`, one JSON entry per line, and each committed offset
+/// in `commits/<key>`. Names are escaped with `encode_name`.
+pub struct FileStorage {
+    root: PathBuf,
+    next_offsets: HashMap<String, usize>,
+    file_handles: HashMap<String, FileHandle>,
+    // topic -> (message offset -> file_ptr)
+    index: HashMap<String, HashMap<usize, u64>>,
+    // in a cell since `committed` and `commits` read files through `&self`.
+    io: RefCell<IoStats>,
+}
+
+impl FileStorage {
+    /// Opens the storage under `root`, indexing any logs left by a previous run.
+    pub fn open(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let mut storage = Self {
+            root: root.into(),
+            next_offsets: HashMap::new(),
+            file_handles: HashMap::new(),
+            index: HashMap::new(),
+            io: RefCell::default(),
+        };
+        storage.build_index().context("building index")?;
+        Ok(storage)
+    }
+
+    // indexes every topic's log, spreading the files over a few threads
+    // since startup has to fit in Maelstrom's init timeout.
+    fn build_index(&mut self) -> anyhow::Result<()> {
+        let topics_dir = self.root.join("topics");
+        if !topics_dir.is_dir() {
+            return Ok(());
+        }
+
+        let mut logs = Vec::new();
+        for dir_entry in std::fs::read_dir(&topics_dir).context("list topics dir")? {
+            let dir_entry = dir_entry.context("read topics dir entry")?;
+            let path = dir_entry.path().join("log");
+            if !path.is_file() {
+                continue;
+            }
+            let name = dir_entry.file_name();
+            let Some(topic) = name.to_str().and_then(decode_name) else {
+                eprintln!("skipping unrecognised topic dir {:?}", dir_entry.path());
+                continue;
+            };
+            logs.push((topic, path));
+        }
+
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |n| n.get())
+            .min(INDEX_THREADS);
+        let chunk = logs.len().div_ceil(threads).max(1);
+        let indexed = std::thread::scope(|scope| {
+            let workers: Vec<_> = logs
+                .chunks(chunk)
+                .map(|logs| {
+                    scope.spawn(move || {
+                        logs.iter()
+                            .map(|(topic, path)| {
+                                index_log(path).with_context(|| format!("index topic {topic}"))
+                            })
+                            .collect::<anyhow::Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|w| w.join().expect("index thread panicked"))
+                .collect::<anyhow::Result<Vec<_>>>()
+        })?;
+
+        let mut bytes_read = 0;
+        for ((topic, _), (index, last_offset, read)) in
+            logs.into_iter().zip(indexed.into_iter().flatten())
+        {
+            bytes_read += read;
+            self.next_offsets
+                .insert(topic.clone(), last_offset.map_or(0, |o| o + 1));
+            self.index.insert(topic, index);
+        }
+        self.io.get_mut().record(
+            "open",
+            OpIo {
+                bytes_read,
+                ..OpIo::default()
+            },
+        );
+        Ok(())
+    }
+
+    fn get_or_create_log_file(&mut self, topic: &str) -> anyhow::Result<&mut FileHandle> {
+        if !self.file_handles.contains_key(topic) {
+            let path = self.log_path(topic);
+
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).context("create all dir, file handles")?; // idempotent: OK if it already exists
+            }
+            // Open for read/write; create if missing
+            let w = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .context("open, append only write log file")?;
+            let r = OpenOptions::new()
+                .read(true)
+                .open(&path)
+                .context("open, read only log")?;
+            let len = r.metadata().context("log file metadata")?.len();
+
+            self.file_handles.insert(
+                topic.to_string(),
+                FileHandle {
+                    r,
+                    w: BufWriter::new(w),
+                    len,
+                    unsynced: false,
+                },
+            );
+        }
+        Ok(self.file_handles.get_mut(topic).unwrap())
+    }
+
+    fn log_path(&self, topic: &str) -> PathBuf {
+        self.root
+            .join("topics")
+            .join(encode_name(topic))
+            .join("log")
+    }
+
+    fn commit_path(&self, key: &str) -> PathBuf {
+        self.root.join("commits").join(encode_name(key))
+    }
+
+    // keys of the files in the commits dir, readable or not.
+    fn commit_keys(&self) -> anyhow::Result<Vec<String>> {
+        let dir = self.root.join("commits");
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+        let mut keys = Vec::new();
+        for dir_entry in std::fs::read_dir(&dir).context("list commits dir")? {
+            let name = dir_entry.context("read commits dir entry")?.file_name();
+            if let Some(key) = name.to_str().and_then(decode_name) {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+// Reads one topic's log: where each offset's entry starts, the highest
+// offset in it and the bytes read. A torn last entry is repaired on the way;
+// unreadable lines before it are skipped, and left for `verify` to report.
+fn index_log(path: &Path) -> anyhow::Result<(HashMap<usize, u64>, Option<usize>, u64)> {
+    let readf = File::open(path).context("build index, read file")?;
+    let mut reader = BufReader::new(readf);
+    let mut index = HashMap::new();
+    let mut location_ptr = 0u64;
+    let mut buf = Vec::new();
+    let mut last_offset = None;
+    loop {
+        buf.clear();
+        let n = reader.read_until(b'\n', &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let Some(line) = buf.strip_suffix(b"\n") else {
+            // the last append was cut short by a crash.
+            repair_torn_tail(path, location_ptr, &buf)?;
+            if let Ok(log_entry) = serde_json::from_slice::<LogEntry>(&buf) {
+                last_offset = last_offset.max(Some(log_entry.offset));
+                index.insert(log_entry.offset, location_ptr);
+            }
+            location_ptr += n as u64;
+            break;
+        };
+        match serde_json::from_slice::<LogEntry>(line) {
+            Ok(log_entry) => {
+                last_offset = last_offset.max(Some(log_entry.offset));
+                index.insert(log_entry.offset, location_ptr);
+            }
+            Err(e) => eprintln!(
+                "{}: skipping bad entry at byte {location_ptr}: {e}",
+                path.display()
+            ),
+        }
+        location_ptr += n as u64;
+    }
+    Ok((index, last_offset, location_ptr))
+}
+
+// Fixes up a log whose last line, starting at byte `start`, has no newline:
+// a complete entry gets its newline back, anything else is cut off.
+fn repair_torn_tail(path: &Path, start: u64, tail: &[u8]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(path)
+        .context("open log for repair")?;
+    if serde_json::from_slice::<LogEntry>(tail).is_ok() {
+        eprintln!("{}: restoring newline after last entry", path.display());
+        file.seek(SeekFrom::End(0))?;
+        file.write_all(b"\n").context("restore newline")?;
+    } else {
+        eprintln!(
+            "{}: dropping {} bytes of a torn write at byte {start}",
+            path.display(),
+            tail.len()
+        );
+        file.set_len(start).context("truncate torn write")?;
+    }
+    Ok(())
+}
+
+impl Storage for FileStorage {
+    fn append(&mut self, topic: &str, message: usize) -> anyhow::Result<usize> {
+        let offset = self.next_offsets.get(topic).copied().unwrap_or(0);
+        let fh = self
+            .get_or_create_log_file(topic)
+            .context("open log file")?;
+
+        let line = format!(
+            "{}\n",
+            serde_json::to_string(&LogEntry { offset, message })?
+        );
+        // the file is opened in append mode, so this lands at `len`. Flushed
+        // before the offset is handed out, so an acked entry survives a crash.
+        let written = (|| -> anyhow::Result<()> {
+            fh.w.write_all(line.as_bytes())?;
+            crate::fail_point!("storage.flush");
+            fh.w.flush().context("flush log")
+        })();
+        if let Err(e) = written {
+            // otherwise the next append would write this entry's offset again.
+            if let Err(discard) = fh.discard_unflushed() {
+                eprintln!("discarding a failed append: {discard:#}");
+            }
+            return Err(e);
+        }
+        let start_ptr = fh.len;
+        fh.len += line.len() as u64;
+        fh.unsynced = true;
+
+        self.index
+            .entry(topic.to_string())
+            .or_default()
+            .insert(offset, start_ptr);
+        self.next_offsets.insert(topic.to_string(), offset + 1);
+        self.io.get_mut().record(
+            "append",
+            OpIo {
+                bytes_written: line.len() as u64,
+                ..OpIo::default()
+            },
+        );
+        crate::fail_point!("storage.append");
+        Ok(offset)
+    }
+
+    fn read_from(&mut self, topic: &str, from: usize) -> anyhow::Result<Vec<LogEntry>> {
+        let Some(entry) = self.index.get(topic) else {
+            // we don't even have this topic, so offset is definitely not there
+            return Ok(vec![]);
+        };
+        let Some(&pos) = entry.get(&from) else {
+            // we have not processed this entry yet.
+            return Ok(vec![]);
+        };
+
+        let fh = self.get_or_create_log_file(topic)?;
+        fh.w.flush()?; // flushing before starting to read.
+        let mut reader = BufReader::new(&fh.r);
+        reader.seek(SeekFrom::Start(pos))?;
+
+        let mut out = Vec::new();
+        let mut bytes_read = 0;
+        for line in reader.split(b'\n') {
+            let line = line?;
+            bytes_read += line.len() as u64 + 1;
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            // bad lines were reported when the log was opened; see `verify`.
+            let Ok(entry) = serde_json::from_slice::<LogEntry>(&line) else {
+                continue;
+            };
+            if entry.offset >= from {
+                out.push(entry);
+            }
+        }
+        self.io.get_mut().record(
+            "read_from",
+            OpIo {
+                bytes_read,
+                ..OpIo::default()
+            },
+        );
+        Ok(out)
+    }
+
+    fn high_water_mark(&self, topic: &str) -> Option<usize> {
+        self.next_offsets.get(topic)?.checked_sub(1)
+    }
+
+    fn commit(&mut self, key: &str, offset: usize) -> anyhow::Result<()> {
+        let path = self.commit_path(key);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent).context("unable to create all dir")?; // idempotent: OK if it already exists
+        }
+
+        let contents = format!("{offset}\n");
+        std::fs::write(&path, &contents).context("write commit to file")?;
+        self.io.get_mut().record(
+            "commit",
+            OpIo {
+                bytes_written: contents.len() as u64,
+                ..OpIo::default()
+            },
+        );
+        crate::fail_point!("storage.commit");
+        Ok(())
+    }
+
+    fn committed(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        let Ok(s) = std::fs::read_to_string(self.commit_path(key)) else {
+            return Ok(None);
+        };
+        self.io.borrow_mut().record(
+            "committed",
+            OpIo {
+                bytes_read: s.len() as u64,
+                ..OpIo::default()
+            },
+        );
+        let offset = s.trim().parse().context("invalid integer in commit file")?;
+        Ok(Some(offset))
+    }
+
+    fn commits(&self) -> anyhow::Result<Vec<(String, usize)>> {
+        let mut out = Vec::new();
+        for key in self.commit_keys()? {
+            if let Ok(Some(offset)) = self.committed(&key) {
+                out.push((key, offset));
+            }
+        }
+        Ok(out)
+    }
+
+    fn verify(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut problems = Vec::new();
+        let topics: Vec<String> = self.index.keys().cloned().collect();
+        for topic in topics {
+            if let Some(fh) = self.file_handles.get_mut(&topic) {
+                fh.w.flush()?;
+            }
+            let mut reader = BufReader::new(File::open(self.log_path(&topic))?);
+            let index = &self.index[&topic];
+            let mut pos = 0u64;
+            let mut expected = 0usize;
+            let mut buf = Vec::new();
+            loop {
+                buf.clear();
+                let n = reader.read_until(b'\n', &mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                match serde_json::from_slice::<LogEntry>(buf.trim_ascii_end()) {
+                    Ok(entry) => {
+                        if entry.offset != expected {
+                            problems.push(format!(
+                                "{topic}: entry at byte {pos} has offset {}, expected {expected}",
+                                entry.offset
+                            ));
+                        }
+                        if index.get(&entry.offset) != Some(&pos) {
+                            problems.push(format!(
+                                "{topic}: index for offset {} doesn't point at byte {pos}",
+                                entry.offset
+                            ));
+                        }
+                        expected = entry.offset + 1;
+                    }
+                    Err(e) => problems.push(format!("{topic}: bad entry at byte {pos}: {e}")),
+                }
+                pos += n as u64;
+            }
+            self.io.get_mut().record(
+                "verify",
+                OpIo {
+                    bytes_read: pos,
+                    ..OpIo::default()
+                },
+            );
+            if index.len() != expected {
+                problems.push(format!(
+                    "{topic}: index has {} entries, log ends at offset {expected}",
+                    index.len()
+                ));
+            }
+            if self.next_offsets.get(&topic) != Some(&expected) {
+                problems.push(format!(
+                    "{topic}: next offset is {:?}, log ends at offset {expected}",
+                    self.next_offsets.get(&topic)
+                ));
+            }
+        }
+        for key in self.commit_keys()? {
+            if let Err(e) = self.committed(&key) {
+                problems.push(format!("commit {key}: {e:#}"));
+            }
+        }
+        Ok(problems)
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        let mut fsyncs = 0;
+        for fh in self.file_handles.values_mut().filter(|fh| fh.unsynced) {
+            fh.w.flush().context("flush log")?;
+            fh.w.get_ref().sync_data().context("fsync log")?;
+            fh.unsynced = false;
+            fsyncs += 1;
+        }
+        self.io.get_mut().record(
+            "sync",
+            OpIo {
+                fsyncs,
+                ..OpIo::default()
+            },
+        );
+        crate::fail_point!("storage.sync");
+        Ok(())
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.io.borrow().clone()
+    }
+}
+
+/// Keeps everything in memory; nothing survives a restart.
+#[derive(Debug, Default)]
+pub struct MemStorage {
+    // a topic's entries are stored at index == offset.
+    topics: HashMap<String, Vec<LogEntry>>,
+    commits: HashMap<String, usize>,
+}
+
+impl Storage for MemStorage {
+    fn append(&mut self, topic: &str, message: usize) -> anyhow::Result<usize> {
+        let log = self.topics.entry(topic.to_string()).or_default();
+        let offset = log.len();
+        log.push(LogEntry { offset, message });
+        Ok(offset)
+    }
+
+    fn read_from(&mut self, topic: &str, from: usize) -> anyhow::Result<Vec<LogEntry>> {
+        let log = self.topics.get(topic).map_or(&[][..], Vec::as_slice);
+        Ok(log.get(from..).unwrap_or_default().to_vec())
+    }
+
+    fn high_water_mark(&self, topic: &str) -> Option<usize> {
+        self.topics.get(topic)?.len().checked_sub(1)
+    }
+
+    fn commit(&mut self, key: &str, offset: usize) -> anyhow::Result<()> {
+        self.commits.insert(key.to_string(), offset);
+        Ok(())
+    }
+
+    fn committed(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        Ok(self.commits.get(key).copied())
+    }
+
+    fn commits(&self) -> anyhow::Result<Vec<(String, usize)>> {
+        Ok(self.commits.iter().map(|(k, v)| (k.clone(), *v)).collect())
+    }
+
+    fn verify(&mut self) -> anyhow::Result<Vec<String>> {
+        // offsets are vector positions, so they can't disagree with the log.
+        Ok(vec![])
+    }
+}
+
+/// The `Storage` calls a `FaultyStorage` can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StorageOp {
+    Append,
+    ReadFrom,
+    Commit,
+    Sync,
+}
+
+/// Wraps another backend and fails chosen calls with an I/O error before
+/// they reach it, so error and recovery paths can be exercised in tests.
+pub struct FaultyStorage<S> {
+    inner: S,
+    // (op, n): the n-th call of op, counting from 0, fails.
+    faults: HashSet<(StorageOp, usize)>,
+    calls: HashMap<StorageOp, usize>,
+}
+
+impl<S: Storage> FaultyStorage<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: HashSet::new(),
+            calls: HashMap::new(),
+        }
+    }
+
+    /// Makes the `nth` call of `op` (counting from 0) fail.
+    pub fn fail(mut self, op: StorageOp, nth: usize) -> Self {
+        self.faults.insert((op, nth));
+        self
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn call(&mut self, op: StorageOp) -> anyhow::Result<()> {
+        let n = self.calls.entry(op).or_default();
+        let nth = *n;
+        *n += 1;
+        if self.faults.contains(&(op, nth)) {
+            return Err(std::io::Error::other(format!(
+                "injected fault: {op:?} #{nth}"
+            )))
+            .context("faulty storage");
+        }
+        Ok(())
+    }
+}
+
+impl<S: Storage> Storage for FaultyStorage<S> {
+    fn append(&mut self, topic: &str, message: usize) -> anyhow::Result<usize> {
+        self.call(StorageOp::Append)?;
+        self.inner.append(topic, message)
+    }
+
+    fn read_from(&mut self, topic: &str, from: usize) -> anyhow::Result<Vec<LogEntry>> {
+        self.call(StorageOp::ReadFrom)?;
+        self.inner.read_from(topic, from)
+    }
+
+    fn high_water_mark(&self, topic: &str) -> Option<usize> {
+        self.inner.high_water_mark(topic)
+    }
+
+    fn commit(&mut self, key: &str, offset: usize) -> anyhow::Result<()> {
+        self.call(StorageOp::Commit)?;
+        self.inner.commit(key, offset)
+    }
+
+    fn committed(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        self.inner.committed(key)
+    }
+
+    fn commits(&self) -> anyhow::Result<Vec<(String, usize)>> {
+        self.inner.commits()
+    }
+
+    fn verify(&mut self) -> anyhow::Result<Vec<String>> {
+        self.inner.verify()
+    }
+
+    fn sync(&mut self) -> anyhow::Result<()> {
+        self.call(StorageOp::Sync)?;
+        self.inner.sync()
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.inner.io_stats()
+    }
+}
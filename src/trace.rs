@@ -0,0 +1,142 @@
+//! Spans for every handled message, in the Chrome trace event format (load it
+//! in `chrome://tracing` or Perfetto). They are written out in chunks as the
+//! node runs and the file is completed at shutdown. Only built with the
+//! `trace` feature.
+//!
+//! Each node writes `data/<node>/trace/trace.json`. Timestamps are wall-clock
+//! microseconds and each node is its own process (`pid` is its index in the
+//! cluster), so the files of one run can be merged into one view.
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::json;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Events are buffered until there are this many, then appended to the file,
+/// so a long run doesn't hold its whole trace in memory.
+pub const CHUNK: usize = 4096;
+
+#[derive(Debug, Serialize)]
+struct Event {
+    name: String,
+    cat: &'static str,
+    ph: &'static str,
+    ts: u64,
+    dur: u64,
+    pid: usize,
+    tid: usize,
+    args: serde_json::Value,
+}
+
+/// One handled message, as `run_node` saw it.
+pub struct Span<'a> {
+    pub kind: &'a str,
+    pub src: &'a str,
+    pub msg_id: Option<u64>,
+    pub trace_id: Option<&'a str>,
+    pub received: Instant,
+    pub started: Instant,
+    pub finished: Instant,
+}
+
+pub struct Trace {
+    node_id: String,
+    pid: usize,
+    // `Instant`s are turned into wall-clock time relative to this pair.
+    base: (Instant, u64),
+    events: Vec<Event>,
+    // the trace file, once the first chunk has been written to it.
+    file: Option<BufWriter<File>>,
+    // why a chunk couldn't be written; later events are dropped and `write`
+    // reports it.
+    failed: Option<anyhow::Error>,
+}
+
+impl Trace {
+    pub fn new(node_id: &str, node_ids: &[String]) -> Self {
+        let wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        Self {
+            node_id: node_id.to_string(),
+            pid: node_ids.iter().position(|n| n == node_id).unwrap_or(0),
+            base: (Instant::now(), wall),
+            events: Vec::with_capacity(CHUNK),
+            file: None,
+            failed: None,
+        }
+    }
+
+    fn micros(&self, at: Instant) -> u64 {
+        self.base.1 + at.saturating_duration_since(self.base.0).as_micros() as u64
+    }
+
+    /// Records one handled message: the time it waited in the queue and the
+    /// time its step took. Spans carry the message's trace id, if any, so an
+    /// operation can be followed across the nodes' files.
+    pub fn step(&mut self, span: Span) {
+        if self.failed.is_some() {
+            return;
+        }
+        let args = json!({ "src": span.src, "msg_id": span.msg_id, "trace_id": span.trace_id });
+        for (name, cat, from, to) in [
+            ("queue", "queue", span.received, span.started),
+            (span.kind, "step", span.started, span.finished),
+        ] {
+            self.events.push(Event {
+                name: name.to_string(),
+                cat,
+                ph: "X",
+                ts: self.micros(from),
+                dur: to.saturating_duration_since(from).as_micros() as u64,
+                pid: self.pid,
+                tid: 0,
+                args: args.clone(),
+            });
+        }
+        if self.events.len() >= CHUNK
+            && let Err(e) = self.flush_chunk()
+        {
+            self.events.clear();
+            self.failed = Some(e);
+        }
+    }
+
+    // appends the buffered events to the file, creating it first if needed.
+    fn flush_chunk(&mut self) -> anyhow::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let dir = crate::storage::node_dir(&self.node_id, "trace");
+                std::fs::create_dir_all(&dir).context("create trace dir")?;
+                let file = File::create(dir.join("trace.json")).context("create trace file")?;
+                let mut file = BufWriter::new(file);
+                let process_name = json!({
+                    "name": "process_name", "ph": "M", "pid": self.pid,
+                    "args": { "name": self.node_id },
+                });
+                write!(file, "{{\"traceEvents\":[{process_name}").context("write trace file")?;
+                self.file.insert(file)
+            }
+        };
+        for event in self.events.drain(..) {
+            file.write_all(b",").context("write trace file")?;
+            serde_json::to_writer(&mut *file, &event).context("write trace file")?;
+        }
+        Ok(())
+    }
+
+    /// Writes the events still buffered and completes the trace file.
+    pub fn write(&mut self) -> anyhow::Result<()> {
+        if let Some(e) = self.failed.take() {
+            return Err(e);
+        }
+        self.flush_chunk()?;
+        let file = self.file.as_mut().expect("created by flush_chunk");
+        file.write_all(b"]}").context("write trace file")?;
+        file.flush().context("write trace file")
+    }
+}
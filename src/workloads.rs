@@ -0,0 +1,7 @@
+//! Wire payloads of each workload, public so that tools outside the node
+//! binaries (load generators, test drivers) speak exactly the same protocol.
+
+pub mod broadcast;
+pub mod echo;
+pub mod kafka;
+pub mod unique_ids;
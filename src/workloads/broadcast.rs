@@ -0,0 +1,54 @@
+//! Challenge 3: broadcast.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// `broadcast` adds messages to the cluster-wide set, `read` returns what
+/// the node has seen, `topology` tells the node its neighbours. Nodes forward
+/// `broadcast` to each other.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Payload {
+    Broadcast {
+        #[serde(flatten)]
+        messages: Messages,
+        /// set on forwards: when the forwarding node sent it, in µs since
+        /// the unix epoch.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sent_at: Option<u64>,
+    },
+    BroadcastOk,
+    Read {
+        /// versioned read: only return messages added after this version.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        read_since: Option<usize>,
+    },
+    ReadOk {
+        messages: Vec<usize>,
+        /// state version, only present in replies to versioned reads.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        version: Option<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+}
+
+/// A broadcast carries either the client's single `message` or a batch of
+/// `messages`; both shapes go through the same handler.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Messages {
+    One { message: usize },
+    Many { messages: Vec<usize> },
+}
+
+impl Messages {
+    pub fn as_slice(&self) -> &[usize] {
+        match self {
+            Messages::One { message } => std::slice::from_ref(message),
+            Messages::Many { messages } => messages,
+        }
+    }
+}
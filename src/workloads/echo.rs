@@ -0,0 +1,11 @@
+//! Challenge 1: echo.
+
+use serde::{Deserialize, Serialize};
+
+/// Clients send `echo`, the node answers `echo_ok` with the same text.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Payload {
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
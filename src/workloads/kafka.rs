@@ -0,0 +1,37 @@
+//! Challenge 5: kafka-style log.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A replicated log per key: `send` appends, `poll` reads from offsets,
+/// `commit_offsets` and `list_committed_offsets` manage consumer progress.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum Payload {
+    Send {
+        #[serde(rename = "key")]
+        topic: String,
+        #[serde(rename = "msg")]
+        message: usize,
+    },
+    SendOk {
+        offset: usize,
+    },
+    Poll {
+        offsets: HashMap<String, usize>,
+    },
+    PollOk {
+        #[serde(rename = "msgs")]
+        messages: HashMap<String, Vec<(usize, usize)>>,
+    },
+    CommitOffsets {
+        offsets: HashMap<String, usize>,
+    },
+    CommitOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: HashMap<String, usize>,
+    },
+}
@@ -0,0 +1,13 @@
+//! Challenge 2: unique ID generation.
+
+use crate::ids::Id;
+use serde::{Deserialize, Serialize};
+
+/// Clients send `generate`, the node answers `generate_ok` with an id no
+/// other `generate` in the cluster ever gets.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Payload {
+    Generate,
+    GenerateOk { id: Id },
+}
@@ -0,0 +1,143 @@
+use flyio_dist::audit::{AUDIT_CAPACITY, AuditWriter, Envelope};
+use flyio_dist::workloads::echo;
+use flyio_dist::{Body, Init, InitPayload, Message, WithExtra, send_to_many};
+use std::io::Write;
+
+fn cause(msg_id: u64) -> Envelope {
+    Envelope {
+        src: "c1".to_string(),
+        dst: "n1".to_string(),
+        kind: "broadcast".to_string(),
+        msg_id: Some(msg_id),
+        in_reply_to: None,
+        trace_id: None,
+    }
+}
+
+#[test]
+fn attributes_written_lines_to_the_cause() {
+    let mut out = AuditWriter::new(Vec::new());
+    out.begin(cause(1));
+    // a message may reach the writer in several pieces
+    out.write_all(br#"{"src":"n1","dest":"n2","body":{"type":"gossip","#)
+        .unwrap();
+    out.write_all(b"\"msg_id\":4}}\n").unwrap();
+    out.write_all(b"{\"src\":\"n1\",\"dest\":\"c1\",\"body\":{\"type\":\"broadcast_ok\",\"in_reply_to\":1}}\n")
+        .unwrap();
+    out.end();
+    // written outside of a step: not attributed to anything
+    out.write_all(b"{\"src\":\"n1\",\"dest\":\"c2\",\"body\":{\"type\":\"error\"}}\n")
+        .unwrap();
+
+    let records: Vec<_> = out.records().collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].cause, cause(1));
+    let effects: Vec<_> = records[0]
+        .effects
+        .iter()
+        .map(|e| (e.dst.as_str(), e.kind.as_str(), e.msg_id, e.in_reply_to))
+        .collect();
+    assert_eq!(
+        effects,
+        vec![
+            ("n2", "gossip", Some(4), None),
+            ("c1", "broadcast_ok", None, Some(1))
+        ]
+    );
+}
+
+#[test]
+fn keeps_only_the_latest_records() {
+    let mut out = AuditWriter::new(std::io::sink());
+    for i in 0..(AUDIT_CAPACITY as u64 + 10) {
+        out.begin(cause(i));
+        out.end();
+    }
+    let ids: Vec<_> = out.records().map(|r| r.cause.msg_id.unwrap()).collect();
+    assert_eq!(ids.len(), AUDIT_CAPACITY);
+    assert_eq!(ids[0], 10);
+}
+
+#[test]
+fn sent_messages_are_recorded_as_if_parsed() {
+    let mut payload = WithExtra::from(echo::Payload::EchoOk {
+        echo: "a \"quoted\"\nline".to_string(),
+    });
+    payload.extra.insert("trace_id".to_string(), "op\t1".into());
+    let msg = Message {
+        src: "n1".to_string(),
+        dst: "c1".to_string(),
+        body: Body {
+            msg_id: Some(2),
+            in_reply_to: Some(1),
+            payload,
+        },
+    };
+    let mut written = Vec::new();
+    let mut out = AuditWriter::new(&mut written);
+    out.begin(cause(1));
+    msg.send(&mut out).unwrap();
+    send_to_many(&mut out, "n1", ["n2", "n3"], &msg.body).unwrap();
+    out.end();
+    let effects = out.records().next().unwrap().effects.clone();
+    let (sent, sent_to_nodes) = (out.sent()["echo_ok"], out.sent_to_nodes());
+    drop(out);
+
+    assert_eq!(
+        std::str::from_utf8(&written)
+            .unwrap()
+            .lines()
+            .next()
+            .unwrap(),
+        serde_json::to_string(&msg).unwrap()
+    );
+    let parsed: Vec<_> = written
+        .split_inclusive(|b| *b == b'\n')
+        .map(|l| Envelope::peek(l.strip_suffix(b"\n").unwrap()).unwrap())
+        .collect();
+    assert_eq!(effects, parsed);
+    assert_eq!(parsed[0].kind, "echo_ok");
+    assert_eq!(parsed[2].trace_id.as_deref(), Some("op\t1"));
+    assert_eq!(sent, 3);
+    assert_eq!(sent_to_nodes, 2);
+}
+
+#[test]
+fn sent_bodies_of_any_shape_are_recorded_as_if_parsed() {
+    let init = Message {
+        src: "n1".to_string(),
+        dst: "c1".to_string(),
+        body: Body {
+            msg_id: Some(1),
+            in_reply_to: None,
+            payload: InitPayload::Init(Init {
+                node_id: "n1".to_string(),
+                node_ids: vec!["n1".to_string()],
+            }),
+        },
+    };
+    let init_ok = Message {
+        body: Body {
+            msg_id: None,
+            in_reply_to: Some(1),
+            payload: InitPayload::InitOk,
+        },
+        ..init.clone()
+    };
+    let mut written = Vec::new();
+    let mut out = AuditWriter::new(&mut written);
+    out.begin(cause(1));
+    init.send(&mut out).unwrap();
+    init_ok.send(&mut out).unwrap();
+    out.end();
+    let effects = out.records().next().unwrap().effects.clone();
+    drop(out);
+
+    let parsed: Vec<_> = written
+        .split_inclusive(|b| *b == b'\n')
+        .map(|l| Envelope::peek(l.strip_suffix(b"\n").unwrap()).unwrap())
+        .collect();
+    assert_eq!(effects, parsed);
+    assert_eq!(parsed[0].kind, "init");
+    assert_eq!(parsed[1].kind, "init_ok");
+}
@@ -0,0 +1,61 @@
+//! Needs `cargo test --features client`.
+#![cfg(feature = "client")]
+
+use flyio_dist::client::Client;
+use flyio_dist::workloads::{broadcast, echo, unique_ids};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn talks_to_a_node_binary() {
+    let mut client = Client::new("c1");
+    let mut input = vec![
+        r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#
+            .to_string(),
+    ];
+    let echo = client.request(
+        "n1",
+        echo::Payload::Echo {
+            echo: "hi".to_string(),
+        },
+    );
+    input.push(client.line(&echo).unwrap());
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for line in &input {
+            writeln!(stdin, "{line}").unwrap();
+        }
+    }
+    let output = child.wait_with_output().unwrap();
+    let replies: Vec<_> = std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .filter_map(|l| client.parse_reply::<echo::Payload>(l).unwrap())
+        .collect();
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].body.in_reply_to, echo.body.msg_id);
+    assert_eq!(
+        replies[0].body.payload,
+        echo::Payload::EchoOk {
+            echo: "hi".to_string()
+        }
+    );
+}
+
+#[test]
+fn msg_ids_count_up_per_client() {
+    let mut client = Client::new("c3");
+    let a = client.request("n1", unique_ids::Payload::Generate);
+    let b = client.request("n2", broadcast::Payload::Read { read_since: None });
+    assert_eq!((a.body.msg_id, b.body.msg_id), (Some(1), Some(2)));
+    assert_eq!(
+        client.line(&b).unwrap(),
+        r#"{"src":"c3","dest":"n2","body":{"msg_id":2,"in_reply_to":null,"type":"read"}}"#
+    );
+}
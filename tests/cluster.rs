@@ -0,0 +1,247 @@
+//! Runs several nodes in one process through `cluster::run_cluster`.
+
+use flyio_dist::cluster::{self, run_cluster};
+use flyio_dist::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    // from a client: ask node `from` for its id.
+    Fetch { from: String },
+    FetchOk { value: String },
+    Get,
+    GetOk { value: String },
+    // sent back and forth between two nodes forever.
+    Chat { with: String },
+}
+
+struct FetchNode {
+    id: String,
+    msg_id_seq: u64,
+    // our get's msg_id -> the fetch waiting for it.
+    waiting: HashMap<u64, Message<Payload>>,
+}
+
+impl Node<(), Payload> for FetchNode {
+    fn from_init(_: (), init: Init) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: init.node_id,
+            msg_id_seq: 1,
+            waiting: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        message: Message<Payload>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        let in_reply_to = message.body.in_reply_to;
+        let mut reply = message.to_reply(Some(&mut self.msg_id_seq));
+        match std::mem::replace(&mut reply.body.payload, Payload::Get) {
+            Payload::Fetch { from } => {
+                let msg_id = self.msg_id_seq;
+                self.msg_id_seq += 1;
+                Message {
+                    src: self.id.clone(),
+                    dst: from,
+                    body: Body {
+                        msg_id: Some(msg_id),
+                        in_reply_to: None,
+                        payload: Payload::Get,
+                    },
+                }
+                .send(writer)?;
+                self.waiting.insert(msg_id, reply);
+            }
+            Payload::Get => {
+                reply.body.payload = Payload::GetOk {
+                    value: self.id.clone(),
+                };
+                reply.send(writer)?;
+            }
+            Payload::GetOk { value } => {
+                if let Some(mut fetch) = in_reply_to.and_then(|id| self.waiting.remove(&id)) {
+                    fetch.body.payload = Payload::FetchOk { value };
+                    fetch.send(writer)?;
+                }
+            }
+            Payload::Chat { with } => Message {
+                src: self.id.clone(),
+                dst: with,
+                body: Body {
+                    msg_id: None,
+                    in_reply_to: None,
+                    payload: Payload::Chat {
+                        with: self.id.clone(),
+                    },
+                },
+            }
+            .send(writer)?,
+            Payload::FetchOk { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn run(nodes: &[&str], input: &str) -> anyhow::Result<Vec<Value>> {
+    let output = Shared::default();
+    let node_ids: Vec<String> = nodes.iter().map(|n| n.to_string()).collect();
+    run_cluster::<_, FetchNode, _>(
+        (),
+        &node_ids,
+        Cursor::new(input.to_string()),
+        output.clone(),
+    )?;
+    let out = output.0.lock().unwrap();
+    Ok(std::str::from_utf8(&out)?
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect())
+}
+
+#[test]
+fn nodes_talk_in_process_and_clients_over_the_streams() {
+    let out = run(
+        &["n1", "n2", "n3"],
+        concat!(
+            r#"{"src":"c1","dest":"n1","body":{"type":"fetch","msg_id":1,"from":"n2"}}"#,
+            "\n",
+            r#"{"src":"c1","dest":"n3","body":{"type":"fetch","msg_id":2,"from":"n1"}}"#,
+            "\n",
+            r#"{"src":"c2","dest":"n2","body":{"type":"get","msg_id":1}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    // no init_oks and no gets between nodes: only the replies to clients.
+    let mut replies: Vec<_> = out
+        .iter()
+        .map(|m| {
+            (
+                m["dest"].as_str().unwrap(),
+                m["body"]["in_reply_to"].as_u64().unwrap(),
+                m["src"].as_str().unwrap(),
+                m["body"]["value"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    replies.sort();
+    assert_eq!(
+        replies,
+        [
+            ("c1", 1, "n1", "n2"),
+            ("c1", 2, "n3", "n1"),
+            ("c2", 1, "n2", "n2")
+        ]
+    );
+}
+
+#[test]
+fn input_for_other_nodes_is_dropped() {
+    let out = run(
+        &["n1"],
+        concat!(
+            r#"{"src":"c1","dest":"n9","body":{"type":"get","msg_id":1}}"#,
+            "\n",
+            "not json\n",
+            r#"{"src":"c1","dest":"n1","body":{"type":"get","msg_id":2}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["body"]["in_reply_to"], 2);
+}
+
+#[test]
+fn a_node_listed_twice_is_an_error() {
+    assert!(run(&["n1", "n1"], "").is_err());
+    assert!(run(&[], "").is_err());
+}
+
+#[test]
+fn nodes_that_keep_talking_are_shut_down() {
+    let started = Instant::now();
+    let out = run(
+        &["n1", "n2"],
+        concat!(
+            r#"{"src":"c1","dest":"n1","body":{"type":"chat","with":"n2"}}"#,
+            "\n",
+        ),
+    )
+    .unwrap();
+    assert!(out.is_empty(), "{out:?}");
+    assert!(started.elapsed() < cluster::MAX_DRAIN + Duration::from_secs(1));
+}
+
+#[test]
+fn broadcast_runs_as_a_cluster() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_broadcast"))
+        .env(cluster::NODES_ENV, "n1,n2,n3")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next = || -> Value { serde_json::from_str(&stdout.next().unwrap().unwrap()).unwrap() };
+
+    writeln!(
+        stdin,
+        r#"{{"src":"c1","dest":"n1","body":{{"type":"broadcast","msg_id":1,"message":5}}}}"#
+    )
+    .unwrap();
+    let ok = next();
+    assert_eq!(
+        (ok["src"].as_str(), ok["body"]["type"].as_str()),
+        (Some("n1"), Some("broadcast_ok"))
+    );
+    // the forwards stay in the process; read until they have landed.
+    for (dest, msg_id) in [("n2", 2), ("n3", 100)] {
+        let mut msg_id = msg_id;
+        loop {
+            writeln!(
+                stdin,
+                r#"{{"src":"c1","dest":"{dest}","body":{{"type":"read","msg_id":{msg_id}}}}}"#
+            )
+            .unwrap();
+            let read = next();
+            assert_eq!(read["body"]["type"], "read_ok", "{read}");
+            assert_eq!(read["src"], dest);
+            if read["body"]["messages"] == serde_json::json!([5]) {
+                break;
+            }
+            assert!(read["body"]["messages"] == serde_json::json!([]), "{read}");
+            msg_id += 1;
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    drop(stdin);
+    // nothing but the replies above reached stdout, and the process ends.
+    let started = Instant::now();
+    assert!(stdout.next().is_none());
+    assert!(child.wait().unwrap().success());
+    assert!(started.elapsed() < cluster::MAX_DRAIN + Duration::from_secs(1));
+}
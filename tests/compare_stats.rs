@@ -0,0 +1,76 @@
+use serde_json::json;
+use std::process::Command;
+
+fn compare(name: &str, before: serde_json::Value, after: serde_json::Value) -> String {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let (b, a) = (dir.join("before.json"), dir.join("after.json"));
+    std::fs::write(&b, before.to_string()).unwrap();
+    std::fs::write(&a, after.to_string()).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_compare_stats"))
+        .arg(&b)
+        .arg(&a)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn row<'a>(report: &'a str, metric: &str) -> Vec<&'a str> {
+    report
+        .lines()
+        .find(|l| l.split_whitespace().next() == Some(metric))
+        .unwrap_or_else(|| panic!("no {metric} in {report}"))
+        .split_whitespace()
+        .collect()
+}
+
+#[test]
+fn compares_admin_stats() {
+    let stats = |msgs_per_op: f64, bytes: u64, syncs: u64| {
+        json!({
+            "msgs_per_op": msgs_per_op,
+            "slow": 0,
+            "sent_sizes": {"a_ok": {"total": bytes}, "b_ok": {"total": 100}},
+            "node": {"sync": {"syncs": syncs}, "ids": {"clock_skew": {"behind": syncs}}},
+        })
+    };
+    // a whole admin_stats_ok message on one side, the bare stats on the other.
+    let before = json!({"src": "n1", "dest": "c1", "body": {"type": "admin_stats_ok", "stats": stats(20.0, 300, 0)}});
+    let report = compare("compare-stats", before, stats(15.0, 100, 4));
+    assert_eq!(
+        row(&report, "msgs_per_op"),
+        ["msgs_per_op", "20.00", "15.00", "-25.0%"]
+    );
+    assert_eq!(
+        row(&report, "bytes_sent"),
+        ["bytes_sent", "400.00", "200.00", "-50.0%"]
+    );
+    assert_eq!(row(&report, "fsyncs")[3], "new");
+    assert_eq!(row(&report, "clock_behind")[1..3], ["0.00", "4.00"]);
+    assert_eq!(row(&report, "slow")[3], "0.0%");
+}
+
+#[test]
+fn compares_trace_percentiles() {
+    let trace = |durs: &[u64]| {
+        let events: Vec<_> = durs
+            .iter()
+            .map(|d| json!({"name": "send", "cat": "step", "ph": "X", "dur": d}))
+            .collect();
+        json!({ "traceEvents": events })
+    };
+    let report = compare(
+        "compare-trace",
+        trace(&[10, 20, 30, 40, 1000]),
+        trace(&[10, 10, 20, 20, 30]),
+    );
+    assert_eq!(row(&report, "step_us_p50")[1..3], ["30.00", "20.00"]);
+    assert_eq!(row(&report, "step_us_p99")[1..3], ["1000.00", "30.00"]);
+}
@@ -0,0 +1,170 @@
+//! Crash and error windows hit through `FLYIO_FAILPOINTS`; needs
+//! `cargo test --features failpoints`.
+#![cfg(feature = "failpoints")]
+
+use serde_json::{Value, json};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+/// Runs `bin` as n1 with init followed by `bodies` from c1, returns the
+/// process output and its stdout lines after init_ok.
+fn run(bin: &str, cwd: &Path, failpoints: &str, bodies: &[Value]) -> (Output, Vec<Value>) {
+    run_with(bin, cwd, failpoints, &[], bodies)
+}
+
+/// Like `run`, with extra environment variables.
+fn run_with(
+    bin: &str,
+    cwd: &Path,
+    failpoints: &str,
+    envs: &[(&str, &str)],
+    bodies: &[Value],
+) -> (Output, Vec<Value>) {
+    let mut child = Command::new(bin)
+        .current_dir(cwd)
+        .env("FLYIO_FAILPOINTS", failpoints)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn node binary");
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        let init = json!({"src": "c0", "dest": "n1",
+            "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}});
+        writeln!(stdin, "{init}").unwrap();
+        for (i, body) in bodies.iter().enumerate() {
+            let mut body = body.clone();
+            body["msg_id"] = json!(i + 1);
+            // the node may already be gone.
+            let _ = writeln!(
+                stdin,
+                "{}",
+                json!({"src": "c1", "dest": "n1", "body": body})
+            );
+        }
+    }
+    let output = child.wait_with_output().expect("wait for node");
+    let lines = std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .skip(1)
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    (output, lines)
+}
+
+#[test]
+fn panic_before_a_reply_fails_only_that_request() {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-panic", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // send #1 is init_ok.
+    let (output, lines) = run(
+        env!("CARGO_BIN_EXE_echo"),
+        &dir,
+        "send=panic@2",
+        &[
+            json!({"type": "echo", "echo": "lost"}),
+            json!({"type": "echo", "echo": "kept"}),
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert_eq!(lines[0]["body"]["code"], 13);
+    assert_eq!(lines[1]["body"]["echo"], "kept");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn crash_between_append_and_reply_keeps_the_entry() {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-failpoint", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (output, lines) = run(
+        env!("CARGO_BIN_EXE_kafka"),
+        &dir,
+        "storage.append=exit@2",
+        &[
+            json!({"type": "send", "key": "k", "msg": 10}),
+            json!({"type": "send", "key": "k", "msg": 11}),
+        ],
+    );
+    assert!(!output.status.success());
+    assert_eq!(lines.len(), 1, "{lines:?}");
+    assert_eq!(lines[0]["body"]["offset"], 0);
+
+    // both entries reached the log, though only the first was acked.
+    let (output, lines) = run(
+        env!("CARGO_BIN_EXE_kafka"),
+        &dir,
+        "",
+        &[json!({"type": "poll", "offsets": {"k": 0}})],
+    );
+    assert!(output.status.success());
+    assert_eq!(lines[0]["body"]["msgs"]["k"], json!([[0, 10], [1, 11]]));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failed_fsync_fails_the_sends_it_covered() {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-fsync", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (output, lines) = run_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &dir,
+        "storage.sync=error@1",
+        &[("KAFKA_SYNC", "group"), ("KAFKA_SYNC_BATCH", "1")],
+        &[
+            json!({"type": "send", "key": "k", "msg": 10}),
+            json!({"type": "send", "key": "k", "msg": 11}),
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert_eq!(lines[0]["body"]["code"], 13);
+    assert_eq!(lines[0]["body"]["in_reply_to"], 1);
+    assert_eq!(lines[1]["body"]["offset"], 1);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn failed_flush_doesnt_leave_the_entry_behind() {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-flush", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let (output, lines) = run(
+        env!("CARGO_BIN_EXE_kafka"),
+        &dir,
+        "storage.flush=error@2",
+        &[
+            json!({"type": "send", "key": "k", "msg": 10}),
+            json!({"type": "send", "key": "k", "msg": 11}),
+            json!({"type": "send", "key": "k", "msg": 12}),
+            json!({"type": "poll", "offsets": {"k": 0}}),
+        ],
+    );
+    assert!(output.status.success());
+    assert_eq!(lines.len(), 4, "{lines:?}");
+    assert_eq!(lines[1]["body"]["code"], 13);
+    assert_eq!(lines[2]["body"]["offset"], 1);
+    assert_eq!(lines[3]["body"]["msgs"]["k"], json!([[0, 10], [1, 12]]));
+
+    // and nothing of it is on disk either.
+    let (output, lines) = run(
+        env!("CARGO_BIN_EXE_kafka"),
+        &dir,
+        "",
+        &[json!({"type": "poll", "offsets": {"k": 0}})],
+    );
+    assert!(output.status.success());
+    assert_eq!(lines[0]["body"]["msgs"]["k"], json!([[0, 10], [1, 12]]));
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,182 @@
+use flyio_dist::ids::{
+    EPOCH_MS, Id, IdGenerator, NodeSeq, Random128, SEQ_BITS, SkewStats, Snowflake, Ulid,
+};
+use flyio_dist::rng::Rng;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::time::Duration;
+
+const NODES: usize = 16;
+
+// Simulated cluster time: every clock read moves it forward by 1ms with
+// probability 1/`tick_every`, and each node sees it shifted by its own skew.
+fn clock(
+    time: &Rc<Cell<u64>>,
+    rng: &Rc<RefCell<Rng>>,
+    skew: u64,
+    tick_every: u64,
+) -> impl FnMut() -> u64 + 'static {
+    let (time, rng) = (time.clone(), rng.clone());
+    move || {
+        if rng.borrow_mut().below(tick_every) == 0 {
+            time.set(time.get() + 1);
+        }
+        time.get() + skew
+    }
+}
+
+// a sleep that moves simulated time forward instead.
+fn sleep(time: &Rc<Cell<u64>>) -> impl FnMut(Duration) + 'static {
+    let time = time.clone();
+    move |d| time.set(time.get() + d.as_millis() as u64)
+}
+
+fn simulate(seed: u64, ids: usize, tick_every: u64, restart_every: u64) {
+    let rng = Rc::new(RefCell::new(Rng::from_seed(seed)));
+    let time = Rc::new(Cell::new(EPOCH_MS + 1_000_000));
+    let skews: Vec<u64> = (0..NODES).map(|_| rng.borrow_mut().below(100)).collect();
+    let mut nodes: Vec<Snowflake> = (0..NODES)
+        .map(|n| {
+            Snowflake::with_clock(n, clock(&time, &rng, skews[n], tick_every), sleep(&time))
+                .unwrap()
+        })
+        .collect();
+
+    let mut seen = HashSet::with_capacity(ids);
+    let mut restarts = 0;
+    for _ in 0..ids {
+        let n = rng.borrow_mut().below(NODES as u64) as usize;
+        if rng.borrow_mut().below(restart_every) == 0 {
+            nodes[n] =
+                Snowflake::with_clock(n, clock(&time, &rng, skews[n], tick_every), sleep(&time))
+                    .unwrap();
+            restarts += 1;
+        }
+        let id = nodes[n].next_id().unwrap();
+        assert!(
+            seen.insert(id),
+            "duplicate id {id} from node {n} (seed {seed})"
+        );
+    }
+    assert!(restarts > 0);
+}
+
+#[test]
+fn unique_across_restarts_and_skew() {
+    simulate(1, 1_000_000, 64, 500);
+}
+
+#[test]
+fn unique_when_the_sequence_runs_out() {
+    // far more than 4096 ids per node and millisecond.
+    simulate(2, 200_000, 200_000, 10_000);
+}
+
+#[test]
+fn rejects_node_indexes_that_dont_fit() {
+    assert!(Snowflake::with_clock(1024, || EPOCH_MS + 1, |_| {}).is_err());
+}
+
+#[test]
+fn never_reissues_timestamps_when_the_clock_goes_back() {
+    let now = Rc::new(Cell::new(EPOCH_MS + 10_000));
+    let clock = {
+        let now = now.clone();
+        move || now.get()
+    };
+    let mut ids = Snowflake::with_clock(0, clock, sleep(&now)).unwrap();
+    let mut last = ids.next_id().unwrap();
+
+    now.set(now.get() - 50);
+    for _ in 0..100 {
+        let id = ids.next_id().unwrap();
+        assert!(id > last, "{id} after {last}");
+        last = id;
+    }
+    assert_eq!(ids.skew_stats().behind, 100);
+
+    now.set(now.get() - 5_000);
+    assert!(ids.next_id().is_err());
+    assert_eq!(ids.skew_stats().rejected, 1);
+
+    now.set(now.get() + 5_100);
+    assert!(ids.next_id().unwrap() > last);
+    assert_eq!(
+        ids.skew_stats(),
+        SkewStats {
+            behind: 100,
+            stalls: 0,
+            rejected: 1
+        }
+    );
+}
+
+#[test]
+fn fails_instead_of_waiting_for_a_clock_that_went_back() {
+    let now = Rc::new(Cell::new(EPOCH_MS + 10_000));
+    let clock = {
+        let now = now.clone();
+        move || now.get()
+    };
+    let mut ids = Snowflake::with_clock(0, clock, sleep(&now)).unwrap();
+    now.set(now.get() - 10);
+    // the last timestamp's remaining sequence numbers, then nothing.
+    for _ in 0..(1 << SEQ_BITS) - 1 {
+        ids.next_id().unwrap();
+    }
+    let behind = now.get();
+    assert!(ids.next_id().is_err());
+    assert_eq!(now.get(), behind, "waited for the clock");
+    assert_eq!(ids.skew_stats().rejected, 1);
+
+    // caught up, but still in the used-up millisecond: sleeps past it.
+    now.set(now.get() + 10);
+    assert!(ids.next_id().is_ok());
+    assert_eq!(now.get(), behind + 11);
+    assert_eq!(ids.skew_stats().stalls, 1);
+}
+
+#[test]
+fn string_generators_differ_between_nodes() {
+    let mut seen = HashSet::new();
+    let mut generators: Vec<Box<dyn IdGenerator>> = Vec::new();
+    for node in ["n1", "n2", "n3"] {
+        generators.push(Box::new(NodeSeq::new(node)));
+        generators.push(Box::new(Ulid::new(Rng::for_node(node).unwrap())));
+        generators.push(Box::new(Random128::new(Rng::for_node(node).unwrap())));
+    }
+    for _ in 0..1000 {
+        for generator in &mut generators {
+            let id = generator.generate().unwrap();
+            assert!(seen.insert(id.clone()), "duplicate id {id:?}");
+        }
+    }
+}
+
+#[test]
+fn ulids_encode_their_time_first() {
+    let time = Rc::new(Cell::new(1_000));
+    let clock = {
+        let time = time.clone();
+        move || time.get()
+    };
+    let mut ulids = Ulid::with_clock(Rng::from_seed(7), clock);
+    let Id::Text(first) = ulids.generate().unwrap() else {
+        panic!("ulids are strings");
+    };
+    time.set(1_001);
+    let Id::Text(second) = ulids.generate().unwrap() else {
+        panic!("ulids are strings");
+    };
+    assert_eq!(first.len(), 26);
+    assert!(
+        first
+            .chars()
+            .all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+    );
+    // 1000ms in the leading 48 bits, in 5-bit digits.
+    assert_eq!(&first[..10], "00000000Z8");
+    assert_eq!(&second[..10], "00000000Z9");
+    assert!(first < second);
+}
@@ -0,0 +1,1111 @@
+//! Drives the compiled binaries over stdin/stdout the way Maelstrom does and
+//! checks the replies on the wire.
+
+use serde_json::{Value, json};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Run {
+    replies: Vec<Value>,
+}
+
+impl Run {
+    /// Reply to the client request with `msg_id`; panics if there isn't exactly one.
+    fn reply_to(&self, client: &str, msg_id: u64) -> &Value {
+        let found: Vec<_> = self
+            .replies
+            .iter()
+            .filter(|r| r["dest"] == client && r["body"]["in_reply_to"] == msg_id)
+            .collect();
+        assert_eq!(
+            found.len(),
+            1,
+            "expected one reply to {client}/{msg_id}, got {found:?}"
+        );
+        found[0]
+    }
+
+    fn sent_to(&self, dst: &str) -> Vec<&Value> {
+        self.replies.iter().filter(|r| r["dest"] == dst).collect()
+    }
+}
+
+/// Spawns `bin` as node `node_ids[0]`, sends init followed by `requests`
+/// (bodies sent from `c1`, msg_ids assigned from 1), closes stdin and collects
+/// every line written to stdout.
+fn run_node(bin: &str, node_ids: &[&str], cwd: &Path, requests: &[Value]) -> Run {
+    let requests: Vec<_> = requests.iter().map(|body| ("c1", body.clone())).collect();
+    run_node_with(bin, node_ids, cwd, &[], &requests)
+}
+
+/// Like `run_node`, with extra environment variables and each request sent
+/// from the given client.
+fn run_node_with(
+    bin: &str,
+    node_ids: &[&str],
+    cwd: &Path,
+    envs: &[(&str, &str)],
+    requests: &[(&str, Value)],
+) -> Run {
+    let mut child = Command::new(bin)
+        .current_dir(cwd)
+        .envs(envs.iter().copied())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("spawn node binary");
+
+    let mut input = vec![json!({
+        "src": "c0",
+        "dest": node_ids[0],
+        "body": {"type": "init", "msg_id": 1, "node_id": node_ids[0], "node_ids": node_ids},
+    })];
+    for (i, (client, body)) in requests.iter().enumerate() {
+        let mut body = body.clone();
+        body["msg_id"] = json!(i + 1);
+        input.push(json!({"src": client, "dest": node_ids[0], "body": body}));
+    }
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        for line in &input {
+            writeln!(stdin, "{line}").unwrap();
+        }
+    }
+
+    let output = child.wait_with_output().expect("wait for node");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        output.status.success(),
+        "node exited with {}: {stderr}",
+        output.status
+    );
+
+    let mut lines = std::str::from_utf8(&output.stdout).unwrap().lines();
+    let init_ok: Value = serde_json::from_str(lines.next().expect("no init_ok")).unwrap();
+    assert_eq!(init_ok["body"]["type"], "init_ok");
+    assert_eq!(init_ok["body"]["in_reply_to"], 1);
+    assert_eq!(init_ok["src"], node_ids[0]);
+    assert_eq!(init_ok["dest"], "c0");
+
+    let replies: Vec<Value> = lines
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("bad json {l:?}: {e}")))
+        .collect();
+    for reply in &replies {
+        if !reply["body"]["in_reply_to"].is_null() {
+            assert_eq!(reply["src"], node_ids[0], "reply from wrong node: {reply}");
+        }
+        let kind = reply["body"]["type"].as_str().expect("reply without type");
+        assert!(
+            kind.chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+            "type is not snake_case: {kind}"
+        );
+    }
+    Run { replies }
+}
+
+// a fresh directory under the system temp dir, removed again on drop, so
+// it goes away even when the test fails.
+struct ScratchDir(PathBuf);
+
+impl std::ops::Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn scratch_dir(name: &str) -> ScratchDir {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    ScratchDir(dir)
+}
+
+#[test]
+fn echo_replies_with_the_same_text() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_echo"),
+        &["n1"],
+        &scratch_dir("echo-replies-with-the-same-text"),
+        &[
+            json!({"type": "echo", "echo": "hello"}),
+            json!({"type": "echo", "echo": "again"}),
+        ],
+    );
+    assert_eq!(run.replies.len(), 2);
+    let first = run.reply_to("c1", 1);
+    assert_eq!(first["body"]["type"], "echo_ok");
+    assert_eq!(first["body"]["echo"], "hello");
+    assert_eq!(run.reply_to("c1", 2)["body"]["echo"], "again");
+}
+
+#[test]
+fn unique_ids_report_clock_skew_in_stats() {
+    let dir = scratch_dir("unique-ids-summary");
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_unique_ids"),
+        &["n1"],
+        &dir,
+        &[("FLYIO_SUMMARY_DIR", "summaries")],
+        &[
+            ("c1", json!({"type": "generate"})),
+            ("c1", json!({"type": "admin_stats"})),
+        ],
+    );
+    let skew = &run.reply_to("c1", 2)["body"]["stats"]["node"]["ids"]["clock_skew"];
+    assert_eq!(skew, &json!({"behind": 0, "stalls": 0, "rejected": 0}));
+    let summary: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("summaries/n1.json")).unwrap()).unwrap();
+    assert_eq!(summary["stats"]["node"]["ids"]["clock_skew"], *skew);
+}
+
+#[test]
+fn unique_ids_are_unique() {
+    let requests: Vec<_> = (0..50)
+        .map(|_| ("c1", json!({"type": "generate"})))
+        .collect();
+    for strategy in ["snowflake", "ulid", "node-seq", "random"] {
+        let run = run_node_with(
+            env!("CARGO_BIN_EXE_unique_ids"),
+            &["n1", "n2"],
+            &scratch_dir("unique-ids-are-unique"),
+            &[("UNIQUE_IDS_STRATEGY", strategy)],
+            &requests,
+        );
+        let mut ids = HashSet::new();
+        for msg_id in 1..=50 {
+            let reply = run.reply_to("c1", msg_id);
+            assert_eq!(reply["body"]["type"], "generate_ok");
+            let id = reply["body"]["id"].to_string();
+            assert!(ids.insert(id.clone()), "{strategy}: duplicate id {id}");
+        }
+    }
+}
+
+#[test]
+fn random_ids_repeat_under_a_pinned_seed() {
+    let requests: Vec<_> = (0..5)
+        .map(|_| ("c1", json!({"type": "generate"})))
+        .collect();
+    let ids = |seed: &str| -> Vec<Value> {
+        let run = run_node_with(
+            env!("CARGO_BIN_EXE_unique_ids"),
+            &["n1"],
+            &scratch_dir("random-ids-repeat-under-a-pinned-seed"),
+            &[("UNIQUE_IDS_STRATEGY", "random"), ("FLYIO_SEED", seed)],
+            &requests,
+        );
+        (1..=5)
+            .map(|msg_id| run.reply_to("c1", msg_id)["body"]["id"].clone())
+            .collect()
+    };
+    assert_eq!(ids("7"), ids("7"));
+    assert_ne!(ids("7"), ids("8"));
+}
+
+#[test]
+fn broadcast_read_and_topology() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("broadcast-read-and-topology"),
+        &[
+            json!({"type": "topology", "topology": {"n1": []}}),
+            json!({"type": "broadcast", "message": 7}),
+            json!({"type": "broadcast", "message": 9}),
+            json!({"type": "read"}),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["type"], "topology_ok");
+    assert_eq!(run.reply_to("c1", 2)["body"]["type"], "broadcast_ok");
+    assert_eq!(run.reply_to("c1", 3)["body"]["type"], "broadcast_ok");
+    let read = run.reply_to("c1", 4);
+    assert_eq!(read["body"]["type"], "read_ok");
+    assert_eq!(read["body"]["messages"], json!([7, 9]));
+    assert!(read["body"].get("version").is_none());
+}
+
+#[test]
+fn broadcast_versioned_reads() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("broadcast-versioned-reads"),
+        &[
+            json!({"type": "broadcast", "message": 1}),
+            json!({"type": "read", "read_since": 0}),
+            json!({"type": "broadcast", "message": 2}),
+            json!({"type": "broadcast", "message": 3}),
+            json!({"type": "read", "read_since": 1}),
+            json!({"type": "read", "read_since": 99}),
+        ],
+    );
+    let first = &run.reply_to("c1", 2)["body"];
+    assert_eq!(first["messages"], json!([1]));
+    assert_eq!(first["version"], 1);
+    let delta = &run.reply_to("c1", 5)["body"];
+    assert_eq!(delta["messages"], json!([2, 3]));
+    assert_eq!(delta["version"], 3);
+    let ahead = &run.reply_to("c1", 6)["body"];
+    assert_eq!(ahead["messages"], json!([]));
+    assert_eq!(ahead["version"], 3);
+}
+
+#[test]
+fn broadcast_accepts_batches() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("broadcast-accepts-batches"),
+        &[
+            json!({"type": "broadcast", "message": 1}),
+            json!({"type": "broadcast", "messages": [2, 3]}),
+            json!({"type": "broadcast", "messages": []}),
+            json!({"type": "read"}),
+        ],
+    );
+    for msg_id in 1..=3 {
+        assert_eq!(run.reply_to("c1", msg_id)["body"]["type"], "broadcast_ok");
+    }
+    assert_eq!(run.reply_to("c1", 4)["body"]["messages"], json!([1, 2, 3]));
+}
+
+#[test]
+fn broadcast_forwards_to_every_peer() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1", "n2", "n3"],
+        &scratch_dir("broadcast-forwards-to-every-peer"),
+        &[json!({"type": "broadcast", "message": 3})],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["type"], "broadcast_ok");
+    for peer in ["n2", "n3"] {
+        let sent = run.sent_to(peer);
+        assert_eq!(sent.len(), 1, "expected one message to {peer}: {sent:?}");
+        assert_eq!(sent[0]["body"]["type"], "broadcast");
+        assert_eq!(sent[0]["body"]["message"], 3);
+    }
+}
+
+#[test]
+fn kafka_send_poll_commit() {
+    let dir = scratch_dir("kafka-send-poll-commit");
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            json!({"type": "send", "key": "k1", "msg": 10}),
+            json!({"type": "send", "key": "k1", "msg": 11}),
+            json!({"type": "send", "key": "k2", "msg": 20}),
+            json!({"type": "poll", "offsets": {"k1": 0, "k2": 0}}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+            json!({"type": "list_committed_offsets", "keys": ["k1", "k2"]}),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["offset"], 0);
+    assert_eq!(run.reply_to("c1", 2)["body"]["offset"], 1);
+    assert_eq!(run.reply_to("c1", 3)["body"]["offset"], 0);
+
+    let poll = run.reply_to("c1", 4);
+    assert_eq!(poll["body"]["type"], "poll_ok");
+    assert_eq!(poll["body"]["msgs"]["k1"], json!([[0, 10], [1, 11]]));
+    assert_eq!(poll["body"]["msgs"]["k2"], json!([[0, 20]]));
+
+    assert_eq!(run.reply_to("c1", 5)["body"]["type"], "commit_offsets_ok");
+    let listed = run.reply_to("c1", 6);
+    assert_eq!(listed["body"]["type"], "list_committed_offsets_ok");
+    assert_eq!(listed["body"]["offsets"], json!({"k1": 1}));
+}
+
+#[test]
+fn kafka_poll_from_the_middle_and_across_restarts() {
+    let dir = scratch_dir("kafka-restart");
+    let sends: Vec<Value> = (0..5)
+        .map(|m| json!({"type": "send", "key": "k1", "msg": 100 + m}))
+        .collect();
+    let mut requests = sends.clone();
+    requests.push(json!({"type": "poll", "offsets": {"k1": 3}}));
+    let run = run_node(env!("CARGO_BIN_EXE_kafka"), &["n1"], &dir, &requests);
+    assert_eq!(
+        run.reply_to("c1", 6)["body"]["msgs"]["k1"],
+        json!([[3, 103], [4, 104]])
+    );
+
+    // a restarted node picks its logs back up and keeps counting offsets.
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            json!({"type": "send", "key": "k1", "msg": 105}),
+            json!({"type": "poll", "offsets": {"k1": 4}}),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["offset"], 5);
+    assert_eq!(
+        run.reply_to("c1", 2)["body"]["msgs"]["k1"],
+        json!([[4, 104], [5, 105]])
+    );
+}
+
+#[test]
+fn kafka_in_memory_storage_touches_no_files() {
+    let dir = scratch_dir("kafka-memory");
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[("KAFKA_STORAGE", "memory")],
+        &[
+            ("c1", json!({"type": "send", "key": "k1", "msg": 10})),
+            ("c1", json!({"type": "send", "key": "k1", "msg": 11})),
+            ("c1", json!({"type": "poll", "offsets": {"k1": 1}})),
+            (
+                "c1",
+                json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+            ),
+            (
+                "c1",
+                json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+            ),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 2)["body"]["offset"], 1);
+    assert_eq!(
+        run.reply_to("c1", 3)["body"]["msgs"]["k1"],
+        json!([[1, 11]])
+    );
+    assert_eq!(run.reply_to("c1", 5)["body"]["offsets"], json!({"k1": 1}));
+    // with the trace feature the only file is the node's trace.
+    if cfg!(feature = "trace") {
+        assert!(!dir.join("data/n1/kafka").exists());
+    } else {
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
+
+#[test]
+fn kafka_trims_large_polls() {
+    let mut requests = Vec::new();
+    for i in 0..20 {
+        let key = if i % 2 == 0 { "a" } else { "b" };
+        requests.push(("c1", json!({"type": "send", "key": key, "msg": 1000 + i})));
+    }
+    requests.push(("c1", json!({"type": "poll", "offsets": {"a": 0, "b": 3}})));
+    let long_key = "k".repeat(50);
+    requests.push(("c1", json!({"type": "send", "key": long_key, "msg": 1})));
+    requests.push(("c1", json!({"type": "send", "key": long_key, "msg": 2})));
+    requests.push((
+        "c1",
+        json!({"type": "poll", "offsets": {long_key.clone(): 0}}),
+    ));
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &scratch_dir("kafka-trims-large-polls"),
+        &[("KAFKA_STORAGE", "memory"), ("KAFKA_MAX_POLL_BYTES", "45")],
+        &requests,
+    );
+    // each topic gets a prefix, taken in turn.
+    let msgs = &run.reply_to("c1", 21)["body"]["msgs"];
+    assert_eq!(msgs["a"], json!([[0, 1000], [1, 1002]]));
+    assert_eq!(msgs["b"], json!([[3, 1007]]));
+    // a single message is returned even when it alone is over the cap.
+    let msgs = &run.reply_to("c1", 24)["body"]["msgs"];
+    assert_eq!(msgs[&long_key], json!([[0, 1]]));
+}
+
+#[test]
+fn kafka_group_commit_acks_every_send() {
+    let dir = scratch_dir("kafka-group-commit");
+    let mut requests: Vec<_> = (0..5)
+        .map(|i| ("c1", json!({"type": "send", "key": "k", "msg": i})))
+        .collect();
+    requests.push(("c1", json!({"type": "admin_stats"})));
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[("KAFKA_SYNC", "group"), ("KAFKA_SYNC_BATCH", "2")],
+        &requests,
+    );
+    for i in 0..5 {
+        assert_eq!(run.reply_to("c1", i + 1)["body"]["offset"], i);
+    }
+    // how sends batch depends on how fast input arrives, but no batch is
+    // over the limit and every send is either synced or still waiting.
+    let sync = &run.reply_to("c1", 6)["body"]["stats"]["node"];
+    assert!(sync["sync"]["max_batch"].as_u64().unwrap() <= 2);
+    assert_eq!(
+        sync["sync"]["synced_sends"].as_u64().unwrap() + sync["unsynced"].as_u64().unwrap(),
+        5
+    );
+}
+
+#[test]
+fn kafka_reports_hot_keys() {
+    let dir = scratch_dir("kafka-hot-keys");
+    // k1 gets three sends in four.
+    let mut requests: Vec<_> = (0..200)
+        .map(|i| {
+            let key = if i % 4 == 0 {
+                format!("k{}", 2 + i % 3)
+            } else {
+                "k1".to_string()
+            };
+            ("c1", json!({"type": "send", "key": key, "msg": i}))
+        })
+        .collect();
+    requests.push(("c1", json!({"type": "admin_stats"})));
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[("KAFKA_STORAGE", "memory"), ("KAFKA_HOT_SHARE", "0.7")],
+        &requests,
+    );
+    let hot_keys = &run.reply_to("c1", 201)["body"]["stats"]["node"]["hot_keys"];
+    assert_eq!(hot_keys["hot"], json!(["k1"]));
+    assert_eq!(hot_keys["became_hot"], 1);
+    assert_eq!(hot_keys["top"][0], json!(["k1", 0.75]));
+}
+
+#[test]
+fn kafka_logs_to_the_file_in_kafka_log() {
+    let dir = scratch_dir("kafka-log");
+    let log = dir.join("kafka.log");
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            ("KAFKA_STORAGE", "memory"),
+            ("KAFKA_LOG", log.to_str().unwrap()),
+        ],
+        &[("c1", json!({"type": "send", "key": "k1", "msg": 7}))],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["type"], "send_ok");
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        logged.contains("send received: key: k1, message: 7"),
+        "{logged}"
+    );
+}
+
+#[test]
+fn kafka_keeps_state_under_its_data_dir() {
+    let dir = scratch_dir("kafka-layout");
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            json!({"type": "send", "key": "k1", "msg": 1}),
+            json!({"type": "send", "key": "k1.log", "msg": 2}),
+            json!({"type": "send", "key": "../../escape", "msg": 3}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 0, "../../escape": 0}}),
+            json!({"type": "poll", "offsets": {"k1": 0, "k1.log": 0, "../../escape": 0}}),
+        ],
+    );
+    let msgs = &run.reply_to("c1", 5)["body"]["msgs"];
+    assert_eq!(msgs["k1"], json!([[0, 1]]));
+    assert_eq!(msgs["k1.log"], json!([[0, 2]]));
+    assert_eq!(msgs["../../escape"], json!([[0, 3]]));
+
+    let root = dir.join("data/n1/kafka");
+    assert!(root.join("topics/k1/log").is_file());
+    assert!(root.join("topics/k1%2Elog/log").is_file());
+    assert!(root.join("commits/k1").is_file());
+    // nothing but the data dir was created
+    let top: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(top, vec!["data"]);
+}
+
+#[test]
+fn kafka_self_check() {
+    let dir = scratch_dir("kafka-self-check");
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            json!({"type": "send", "key": "k1", "msg": 1}),
+            json!({"type": "send", "key": "k1", "msg": 2}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+            json!({"type": "admin_self_check"}),
+        ],
+    );
+    let healthy = run.reply_to("c1", 4);
+    assert_eq!(healthy["body"]["type"], "admin_self_check_ok");
+    assert_eq!(healthy["body"]["problems"], json!([]));
+
+    // a log with a hole in its offsets and a commit nothing was sent for.
+    let root = dir.join("data/n1/kafka");
+    let log = root.join("topics/k1/log");
+    let mut content = std::fs::read_to_string(&log).unwrap();
+    content.push_str("{\"offset\":5,\"message\":3}\n");
+    std::fs::write(&log, content).unwrap();
+    std::fs::write(root.join("commits/k2"), "3\n").unwrap();
+
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[json!({"type": "admin_self_check"})],
+    );
+    let problems = run.reply_to("c1", 1)["body"]["problems"].clone();
+    let problems: Vec<String> = serde_json::from_value(problems).unwrap();
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.contains("has offset 5, expected 2")),
+        "{problems:?}"
+    );
+    assert!(
+        problems.iter().any(|p| p.contains("commit k2")),
+        "{problems:?}"
+    );
+}
+
+#[test]
+fn kafka_starts_with_a_corrupt_commit_file() {
+    let dir = scratch_dir("kafka-corrupt-commit");
+    let commits = dir.join("data/n1/kafka/commits");
+    std::fs::create_dir_all(&commits).unwrap();
+    std::fs::write(commits.join("k1"), "not a number\n").unwrap();
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            json!({"type": "send", "key": "k1", "msg": 1}),
+            json!({"type": "admin_self_check"}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 0}}),
+            json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["offset"], 0);
+    let problems = run.reply_to("c1", 2)["body"]["problems"].clone();
+    let problems: Vec<String> = serde_json::from_value(problems).unwrap();
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.contains("commit k1") && p.contains("invalid integer")),
+        "{problems:?}"
+    );
+    // a new commit replaces the bad one.
+    assert_eq!(run.reply_to("c1", 4)["body"]["offsets"], json!({"k1": 0}));
+}
+
+#[test]
+fn kafka_rejects_commits_beyond_high_water_mark() {
+    let dir = scratch_dir("kafka-commit-hwm");
+    let run = run_node(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            // commit before anything was sent to the key
+            json!({"type": "commit_offsets", "offsets": {"k1": 0}}),
+            json!({"type": "send", "key": "k1", "msg": 10}),
+            json!({"type": "send", "key": "k1", "msg": 11}),
+            // past the last offset; k1 must not be committed either
+            json!({"type": "commit_offsets", "offsets": {"k1": 1, "k2": 0}}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 2}}),
+            json!({"type": "list_committed_offsets", "keys": ["k1", "k2"]}),
+            json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+            json!({"type": "list_committed_offsets", "keys": ["k1", "k2"]}),
+        ],
+    );
+    for msg_id in [1, 4, 5] {
+        let err = run.reply_to("c1", msg_id);
+        assert_eq!(err["body"]["type"], "error", "request {msg_id}");
+        assert_eq!(err["body"]["code"], 22, "request {msg_id}");
+    }
+    assert_eq!(run.reply_to("c1", 6)["body"]["offsets"], json!({}));
+    assert_eq!(run.reply_to("c1", 7)["body"]["type"], "commit_offsets_ok");
+    assert_eq!(run.reply_to("c1", 8)["body"]["offsets"], json!({"k1": 1}));
+}
+
+#[test]
+fn kafka_per_client_commits() {
+    let dir = scratch_dir("kafka-per-client-commits");
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[("KAFKA_COMMIT_SCOPE", "client")],
+        &[
+            ("c1", json!({"type": "send", "key": "k1", "msg": 10})),
+            ("c1", json!({"type": "send", "key": "k1", "msg": 11})),
+            (
+                "c1",
+                json!({"type": "commit_offsets", "offsets": {"k1": 1}}),
+            ),
+            (
+                "c2",
+                json!({"type": "commit_offsets", "offsets": {"k1": 0}}),
+            ),
+            (
+                "c1",
+                json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+            ),
+            (
+                "c2",
+                json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+            ),
+            (
+                "c3",
+                json!({"type": "list_committed_offsets", "keys": ["k1"]}),
+            ),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 5)["body"]["offsets"], json!({"k1": 1}));
+    assert_eq!(run.reply_to("c2", 6)["body"]["offsets"], json!({"k1": 0}));
+    assert_eq!(run.reply_to("c3", 7)["body"]["offsets"], json!({}));
+}
+
+#[test]
+fn malformed_request_gets_an_error_reply() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_echo"),
+        &["n1"],
+        &scratch_dir("malformed-request-gets-an-error-reply"),
+        &[
+            json!({"type": "echo"}),
+            json!({"type": "echo", "echo": "still alive"}),
+        ],
+    );
+    let err = run.reply_to("c1", 1);
+    assert_eq!(err["body"]["type"], "error");
+    assert_eq!(err["body"]["code"], 12);
+    assert_eq!(run.reply_to("c1", 2)["body"]["echo"], "still alive");
+}
+
+#[test]
+fn admin_messages_are_answered_by_every_binary() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_echo"),
+        &["n1"],
+        &scratch_dir("admin-messages-are-answered-by-every-binary"),
+        &[
+            json!({"type": "echo", "echo": "hi"}),
+            json!({"type": "echo"}),
+            json!({"type": "admin_stats"}),
+            json!({"type": "admin_self_check"}),
+            json!({"type": "admin_set_config", "key": "batch_size", "value": 8}),
+            json!({"type": "admin_bogus"}),
+        ],
+    );
+    let stats = &run.reply_to("c1", 3)["body"];
+    assert_eq!(stats["type"], "admin_stats_ok");
+    assert_eq!(stats["stats"]["received"]["echo"], 1);
+    assert_eq!(stats["stats"]["received"]["admin_stats"], 1);
+    assert_eq!(stats["stats"]["sent"]["echo_ok"], 1);
+    let sizes = &stats["stats"]["sent_sizes"]["echo_ok"];
+    assert_eq!(sizes["count"], 1);
+    let size = sizes["max"].as_u64().unwrap();
+    assert!(size > 0 && sizes["total"] == size);
+    assert_eq!(sizes["buckets"][size.next_power_of_two().to_string()], 1);
+    assert_eq!(stats["stats"]["malformed"], 1);
+    assert!(stats["stats"]["init_ms"].is_u64());
+    assert_eq!(run.reply_to("c1", 4)["body"]["problems"], json!([]));
+    // echo has no settings.
+    assert_eq!(run.reply_to("c1", 5)["body"]["code"], 10);
+    assert_eq!(run.reply_to("c1", 6)["body"]["code"], 12);
+}
+
+#[test]
+fn a_summary_is_written_at_eof() {
+    let dir = scratch_dir("summary");
+    let requests: Vec<_> = (0..3)
+        .map(|i| ("c1", json!({"type": "send", "key": "k", "msg": i})))
+        .collect();
+    run_node_with(
+        env!("CARGO_BIN_EXE_kafka"),
+        &["n1"],
+        &dir,
+        &[
+            ("KAFKA_STORAGE", "memory"),
+            ("FLYIO_SUMMARY_DIR", "summaries"),
+        ],
+        &requests,
+    );
+    let summary: Value =
+        serde_json::from_slice(&std::fs::read(dir.join("summaries/n1.json")).unwrap()).unwrap();
+    assert_eq!(summary["node_id"], "n1");
+    let stats = &summary["stats"];
+    assert_eq!(stats["received"]["send"], 3);
+    assert_eq!(stats["sent"]["send_ok"], 3);
+    assert!(stats["max_queue_depth"].as_u64().unwrap() >= 1);
+    assert!(stats["node"]["hot_keys"].is_object());
+}
+
+#[test]
+fn overloaded_reads_are_shed() {
+    let requests = [
+        ("c1", json!({"type": "broadcast", "message": 1})),
+        ("c1", json!({"type": "read"})),
+        ("c1", json!({"type": "admin_stats"})),
+    ];
+    // any queueing at all is over a 0ms limit.
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("overloaded-reads-are-shed"),
+        &[("FLYIO_SHED_QUEUE_MS", "0")],
+        &requests,
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["type"], "broadcast_ok");
+    assert_eq!(run.reply_to("c1", 2)["body"]["code"], 11);
+    assert_eq!(run.reply_to("c1", 3)["body"]["stats"]["shed"], 1);
+
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("overloaded-reads-are-shed"),
+        &[],
+        &requests,
+    );
+    assert_eq!(run.reply_to("c1", 2)["body"]["messages"], json!([1]));
+    assert_eq!(run.reply_to("c1", 3)["body"]["stats"]["shed"], 0);
+}
+
+#[test]
+fn admin_dump_state() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("admin-dump-state"),
+        &[
+            json!({"type": "broadcast", "message": 7}),
+            json!({"type": "admin_dump_state"}),
+        ],
+    );
+    let dump = &run.reply_to("c1", 2)["body"];
+    assert_eq!(dump["type"], "admin_dump_state_ok");
+    assert_eq!(dump["state"]["seen_messages"], json!([7]));
+    let audit = dump["audit"].as_array().unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!(audit[0]["cause"]["type"], "broadcast");
+    assert_eq!(audit[0]["effects"][0]["type"], "broadcast_ok");
+}
+
+#[test]
+fn requests_over_the_latency_budget_are_counted() {
+    let requests = [
+        ("c1", json!({"type": "echo", "echo": "a"})),
+        ("c1", json!({"type": "echo", "echo": "b"})),
+        ("c1", json!({"type": "admin_stats"})),
+    ];
+    let slow = |budget_ms: &str| {
+        let run = run_node_with(
+            env!("CARGO_BIN_EXE_echo"),
+            &["n1"],
+            &scratch_dir("requests-over-the-latency-budget-are-counted"),
+            &[("FLYIO_LATENCY_BUDGET_MS", budget_ms)],
+            &requests,
+        );
+        run.reply_to("c1", 3)["body"]["stats"]["slow"].clone()
+    };
+    assert_eq!(slow("0"), 2);
+    assert_eq!(slow("60000"), 0);
+}
+
+#[test]
+fn broadcast_reports_msgs_per_op_and_hop_latency() {
+    let run = run_node_with(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1", "n2", "n3"],
+        &scratch_dir("broadcast-reports-msgs-per-op-and-hop-latency"),
+        &[],
+        &[
+            ("c1", json!({"type": "broadcast", "message": 1})),
+            ("c1", json!({"type": "read"})),
+            (
+                "n2",
+                json!({"type": "broadcast", "message": 2, "sent_at": 0}),
+            ),
+            ("c1", json!({"type": "admin_stats"})),
+        ],
+    );
+    let forwards = run.sent_to("n2");
+    assert_eq!(forwards[0]["body"]["message"], 1);
+    assert!(forwards[0]["body"]["sent_at"].as_u64().unwrap() > 0);
+
+    let stats = &run.reply_to("c1", 4)["body"]["stats"];
+    assert_eq!(stats["client_ops"], 2);
+    // one fan-out of the client's broadcast to n2 and n3, plus the
+    // broadcast_ok to n2; n2's broadcast isn't forwarded again.
+    assert_eq!(stats["node_msgs_sent"], 3);
+    assert_eq!(stats["msgs_per_op"], 1.5);
+    assert_eq!(stats["node"]["hop_latency"]["hops"], 1);
+}
+
+#[test]
+fn broadcast_forwards_only_new_messages_once() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1", "n2", "n3"],
+        &scratch_dir("broadcast-forwards-only-new-messages-once"),
+        &[
+            json!({"type": "broadcast", "message": 1}),
+            json!({"type": "broadcast", "message": 1}),
+            json!({"type": "broadcast", "messages": [1, 2]}),
+            json!({"type": "read"}),
+        ],
+    );
+    for msg_id in 1..=3 {
+        assert_eq!(run.reply_to("c1", msg_id)["body"]["type"], "broadcast_ok");
+    }
+    assert_eq!(run.reply_to("c1", 4)["body"]["messages"], json!([1, 2]));
+    for peer in ["n2", "n3"] {
+        let sent: Vec<_> = run
+            .sent_to(peer)
+            .iter()
+            .map(|m| m["body"].clone())
+            .collect();
+        assert_eq!(sent.len(), 2, "{peer}: {sent:?}");
+        assert_eq!(sent[0]["message"], 1);
+        assert_eq!(sent[1]["message"], 2);
+        // forwards come from the node and need no reply.
+        assert!(sent.iter().all(|b| b["msg_id"].is_null()));
+        assert!(run.sent_to(peer).iter().all(|m| m["src"] == "n1"));
+    }
+
+    // a forward arriving back at the node is neither answered nor forwarded.
+    let forward = r#"{"src":"n2","dest":"n1","body":{"type":"broadcast","message":1}}"#;
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_broadcast"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        writeln!(
+            stdin,
+            r#"{{"src":"c0","dest":"n1","body":{{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1","n2","n3"]}}}}"#
+        )
+        .unwrap();
+        writeln!(
+            stdin,
+            r#"{{"src":"c1","dest":"n1","body":{{"type":"broadcast","msg_id":1,"message":1}}}}"#
+        )
+        .unwrap();
+        writeln!(stdin, "{forward}").unwrap();
+        writeln!(stdin, "{forward}").unwrap();
+        writeln!(
+            stdin,
+            r#"{{"src":"c1","dest":"n1","body":{{"type":"read","msg_id":2}}}}"#
+        )
+        .unwrap();
+    }
+    let output = child.wait_with_output().unwrap();
+    let lines: Vec<Value> = std::str::from_utf8(&output.stdout)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let types: Vec<_> = lines
+        .iter()
+        .map(|l| {
+            format!(
+                "{}>{}",
+                l["body"]["type"].as_str().unwrap(),
+                l["dest"].as_str().unwrap()
+            )
+        })
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "init_ok>c0",
+            "broadcast>n2",
+            "broadcast>n3",
+            "broadcast_ok>c1",
+            "read_ok>c1"
+        ]
+    );
+    assert_eq!(lines[4]["body"]["messages"], json!([1]));
+}
+
+#[test]
+fn unknown_body_fields_survive_echo_and_forwarding() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_echo"),
+        &["n1"],
+        &scratch_dir("unknown-body-fields-survive-echo-and-forwarding"),
+        &[json!({"type": "echo", "echo": "hi", "trace": {"id": 7}})],
+    );
+    let reply = &run.reply_to("c1", 1)["body"];
+    assert_eq!(reply["echo"], "hi");
+    assert_eq!(reply["trace"], json!({"id": 7}));
+
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1", "n2"],
+        &scratch_dir("unknown-body-fields-survive-echo-and-forwarding"),
+        &[json!({"type": "broadcast", "message": 3, "trace": "t1"})],
+    );
+    assert_eq!(run.sent_to("n2")[0]["body"]["trace"], "t1");
+    assert!(run.reply_to("c1", 1)["body"].get("trace").is_none());
+}
+
+#[test]
+fn garbage_gets_errors_and_leaves_state_alone() {
+    // each binary gets bodies that parse as JSON but make no sense for it,
+    // then one sensible request whose reply shows the state is intact.
+    let cases = [
+        (
+            env!("CARGO_BIN_EXE_echo"),
+            vec![
+                json!({"type": "echo_ok", "echo": "x", "in_reply_to": 99}),
+                json!({"type": "echo", "echo": 5}),
+            ],
+            json!({"type": "echo", "echo": "still here"}),
+            json!({"echo": "still here"}),
+        ),
+        (
+            env!("CARGO_BIN_EXE_unique_ids"),
+            vec![
+                json!({"type": "generate_ok", "id": 1, "in_reply_to": 99}),
+                json!({"type": "generate", "id": "n1-0"}),
+            ],
+            json!({"type": "admin_self_check"}),
+            json!({"problems": []}),
+        ),
+        (
+            env!("CARGO_BIN_EXE_broadcast"),
+            vec![
+                json!({"type": "broadcast_ok", "in_reply_to": 99}),
+                json!({"type": "read_ok", "messages": [1, 2], "in_reply_to": 98}),
+                json!({"type": "broadcast", "message": -1}),
+                json!({"type": "read", "read_since": u64::MAX}),
+            ],
+            json!({"type": "read"}),
+            json!({"messages": []}),
+        ),
+        (
+            env!("CARGO_BIN_EXE_kafka"),
+            vec![
+                json!({"type": "send_ok", "offset": 7, "in_reply_to": 99}),
+                json!({"type": "poll", "offsets": {"k": u64::MAX}}),
+                json!({"type": "poll", "offsets": {"k": -3}}),
+                json!({"type": "commit_offsets", "offsets": {"k": u64::MAX}}),
+                json!({"type": "list_committed_offsets", "keys": ["k"]}),
+            ],
+            json!({"type": "admin_self_check"}),
+            json!({"problems": []}),
+        ),
+    ];
+    for (bin, garbage, probe, expected) in cases {
+        let mut requests: Vec<_> = garbage.iter().map(|b| ("c1", b.clone())).collect();
+        requests.push(("c1", json!({"type": "no_such_type"})));
+        requests.push(("c1", probe));
+        requests.push(("c1", json!({"type": "admin_stats"})));
+        let run = run_node_with(
+            bin,
+            &["n1"],
+            &scratch_dir("garbage"),
+            &[("KAFKA_STORAGE", "memory")],
+            &requests,
+        );
+        let n = requests.len() as u64;
+        // stray replies go unanswered, everything else gets exactly one reply.
+        for (i, (_, body)) in requests.iter().enumerate() {
+            let answers = run
+                .replies
+                .iter()
+                .filter(|r| r["body"]["in_reply_to"] == i + 1)
+                .count();
+            let stray = body["type"].as_str().unwrap().ends_with("_ok");
+            assert_eq!(answers, usize::from(!stray), "{bin}: {body}");
+        }
+        assert_eq!(run.reply_to("c1", n - 2)["body"]["code"], 12, "{bin}");
+        for (key, value) in expected.as_object().unwrap() {
+            assert_eq!(&run.reply_to("c1", n - 1)["body"][key], value, "{bin}");
+        }
+        assert_eq!(run.reply_to("c1", n)["body"]["stats"]["panics"], 0, "{bin}");
+    }
+}
+
+#[test]
+fn forwards_carry_a_trace_id() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1", "n2"],
+        &scratch_dir("forwards-carry-a-trace-id"),
+        &[
+            json!({"type": "broadcast", "message": 1}),
+            json!({"type": "broadcast", "message": 2, "trace_id": "op-7"}),
+            json!({"type": "admin_dump_state"}),
+        ],
+    );
+    let forwards = run.sent_to("n2");
+    assert_eq!(forwards[0]["body"]["trace_id"], "c1:1");
+    assert_eq!(forwards[1]["body"]["trace_id"], "op-7");
+    // the audit log shows which operation each message belonged to.
+    let audit = &run.reply_to("c1", 3)["body"]["audit"];
+    assert!(audit[0]["cause"].get("trace_id").is_none());
+    assert_eq!(audit[0]["effects"][0]["trace_id"], "c1:1");
+    assert_eq!(audit[1]["cause"]["trace_id"], "op-7");
+    assert_eq!(audit[1]["effects"][0]["trace_id"], "op-7");
+}
+
+#[test]
+fn admin_load_state_restores_a_dump() {
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("admin-load-state-restores-a-dump"),
+        &[
+            json!({"type": "broadcast", "message": 4}),
+            json!({"type": "broadcast", "message": 5}),
+            json!({"type": "admin_dump_state"}),
+        ],
+    );
+    let state = run.reply_to("c1", 3)["body"]["state"].clone();
+
+    let run = run_node(
+        env!("CARGO_BIN_EXE_broadcast"),
+        &["n1"],
+        &scratch_dir("admin-load-state-restores-a-dump"),
+        &[
+            json!({"type": "admin_load_state", "state": state}),
+            json!({"type": "read"}),
+            json!({"type": "admin_load_state", "state": {"seen_messages": "no"}}),
+        ],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["type"], "admin_load_state_ok");
+    assert_eq!(run.reply_to("c1", 2)["body"]["messages"], json!([4, 5]));
+    assert_eq!(run.reply_to("c1", 3)["body"]["code"], 12);
+
+    let run = run_node(
+        env!("CARGO_BIN_EXE_echo"),
+        &["n1"],
+        &scratch_dir("admin-load-state-restores-a-dump"),
+        &[json!({"type": "admin_load_state", "state": {}})],
+    );
+    assert_eq!(run.reply_to("c1", 1)["body"]["code"], 10);
+}
@@ -0,0 +1,73 @@
+use flyio_dist::rng::{Rng, SEED_ENV, node_seed};
+use std::time::Duration;
+
+#[test]
+fn same_seed_same_stream() {
+    let mut a = Rng::from_seed(42);
+    let mut b = Rng::from_seed(42);
+    let xs: Vec<_> = (0..8).map(|_| a.next_u64()).collect();
+    let ys: Vec<_> = (0..8).map(|_| b.next_u64()).collect();
+    assert_eq!(xs, ys);
+    assert_ne!(
+        xs,
+        (0..8)
+            .map(|_| Rng::from_seed(43).next_u64())
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn node_seeds_are_stable_and_distinct() {
+    // pinned, so a run's FLYIO_SEED picks the same streams on every build.
+    assert_eq!(node_seed(7, "n1"), 0x45ba_e183_8a36_3371);
+    assert_eq!(node_seed(7, "n1"), node_seed(7, "n1"));
+    let seeds: std::collections::HashSet<_> = ["n1", "n2", "n10", "n01", ""]
+        .iter()
+        .map(|n| node_seed(7, n))
+        .collect();
+    assert_eq!(seeds.len(), 5);
+    assert_ne!(node_seed(7, "n1"), node_seed(8, "n1"));
+}
+
+#[test]
+fn below_and_choose_stay_in_range() {
+    let mut rng = Rng::from_seed(1);
+    let mut seen = [false; 5];
+    for _ in 0..1000 {
+        let v = rng.below(5);
+        assert!(v < 5);
+        seen[v as usize] = true;
+    }
+    assert!(seen.iter().all(|s| *s));
+    assert_eq!(rng.below(1), 0);
+    assert_eq!(rng.choose::<u8>(&[]), None);
+    assert!([1, 2, 3].contains(rng.choose(&[1, 2, 3]).unwrap()));
+}
+
+#[test]
+fn jitter_stays_within_the_percentage() {
+    let mut rng = Rng::from_seed(3);
+    let base = Duration::from_millis(100);
+    for _ in 0..1000 {
+        let d = rng.jitter(base, 50);
+        assert!(d >= base && d <= Duration::from_millis(150), "{d:?}");
+    }
+    assert_eq!(rng.jitter(base, 0), base);
+    assert_eq!(rng.jitter(Duration::ZERO, 100), Duration::ZERO);
+    // saturates instead of overflowing.
+    assert_eq!(rng.jitter(Duration::MAX, u32::MAX), Duration::MAX);
+}
+
+// the only test here that touches the environment.
+#[test]
+fn for_node_derives_from_the_run_seed() {
+    // SAFETY: no other test in this binary reads or writes the environment.
+    unsafe { std::env::set_var(SEED_ENV, "7") };
+    let mut a = Rng::for_node("n1").unwrap();
+    let mut b = Rng::from_seed(node_seed(7, "n1"));
+    assert_eq!(a.next_u64(), b.next_u64());
+    unsafe { std::env::set_var(SEED_ENV, "seven") };
+    assert!(Rng::for_node("n1").is_err());
+    unsafe { std::env::remove_var(SEED_ENV) };
+    assert!(Rng::for_node("n1").is_ok());
+}
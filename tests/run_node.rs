@@ -0,0 +1,267 @@
+//! Runs a node in-process over byte buffers through `run_node`.
+
+use flyio_dist::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{Cursor, Write};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Payload {
+    Ping,
+    Pong { from: String },
+    // makes PingNode's step return an error.
+    Fail,
+}
+
+struct PingNode {
+    id: String,
+    msg_id_seq: u64,
+}
+
+impl Node<String, Payload> for PingNode {
+    fn from_init(suffix: String, init: Init) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: format!("{}{suffix}", init.node_id),
+            msg_id_seq: 1,
+        })
+    }
+
+    fn step(
+        &mut self,
+        message: Message<Payload>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        let mut reply = message.to_reply(Some(&mut self.msg_id_seq));
+        if let Payload::Fail = reply.body.payload {
+            anyhow::bail!("asked to fail");
+        }
+        if let Payload::Ping = reply.body.payload {
+            reply.body.payload = Payload::Pong {
+                from: self.id.clone(),
+            };
+            reply.send(writer)?;
+        }
+        Ok(())
+    }
+
+    // answers an error body with a pong naming it.
+    fn on_error(
+        &mut self,
+        error: Message<ErrorPayload>,
+        writer: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        let text = error.body.payload.to_string();
+        Message {
+            src: error.dst,
+            dst: error.src,
+            body: Body {
+                msg_id: None,
+                in_reply_to: None,
+                payload: Payload::Pong { from: text },
+            },
+        }
+        .send(writer)
+    }
+}
+
+fn run(input: &str) -> anyhow::Result<Vec<Value>> {
+    let mut output = Vec::new();
+    run_node::<_, PingNode, _>(
+        "-test".to_string(),
+        Cursor::new(input.to_string()),
+        &mut output,
+    )?;
+    Ok(String::from_utf8(output)?
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect())
+}
+
+#[test]
+fn handshake_then_messages() {
+    let out = run(concat!(
+        r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":7,"node_id":"n1","node_ids":["n1"]}}"#,
+        "\n",
+        r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1}}"#,
+        "\n",
+    ))
+    .unwrap();
+
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0]["body"]["type"], "init_ok");
+    assert_eq!(out[0]["body"]["in_reply_to"], 7);
+    assert_eq!(out[0]["dest"], "c0");
+    assert_eq!(out[1]["body"]["type"], "pong");
+    assert_eq!(out[1]["body"]["from"], "n1-test");
+    assert_eq!(out[1]["body"]["in_reply_to"], 1);
+}
+
+#[test]
+fn init_state_reaches_the_node_and_eof_ends_the_run() {
+    let out = run(concat!(
+        r#"{"src":"c0","dest":"n2","body":{"type":"init","msg_id":1,"node_id":"n2","node_ids":["n1","n2"]}}"#,
+        "\n",
+    ))
+    .unwrap();
+    assert_eq!(out.len(), 1);
+    assert_eq!(out[0]["src"], "n2");
+}
+
+#[test]
+fn garbage_init_is_an_error() {
+    assert!(run("not json\n").is_err());
+}
+
+#[test]
+fn no_input_at_all_is_an_error() {
+    assert!(run("").is_err());
+}
+
+const INIT_N1: &str = r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":7,"node_id":"n1","node_ids":["n1"]}}"#;
+const PING: &str = r#"{"src":"c1","dest":"n1","body":{"type":"ping","msg_id":1}}"#;
+
+#[test]
+fn messages_before_init_are_handled_after_it() {
+    let out = run(&format!("{PING}\n{INIT_N1}\n")).unwrap();
+    assert_eq!(out.len(), 2);
+    assert_eq!(out[0]["body"]["type"], "init_ok");
+    assert_eq!(out[1]["body"]["type"], "pong");
+    assert_eq!(out[1]["body"]["in_reply_to"], 1);
+}
+
+#[test]
+fn a_repeated_init_is_answered_again() {
+    let out = run(&format!("{INIT_N1}\n{INIT_N1}\n{PING}\n")).unwrap();
+    let types: Vec<_> = out.iter().map(|m| m["body"]["type"].clone()).collect();
+    assert_eq!(types, ["init_ok", "init_ok", "pong"]);
+    assert_eq!(out[1]["body"]["in_reply_to"], 7);
+}
+
+#[test]
+fn handshake_errors_are_typed() {
+    let err = run(&format!("{PING}\n")).unwrap_err();
+    assert_eq!(err.downcast_ref(), Some(&InitError::NoInit));
+
+    let init_n2 = INIT_N1.replace(r#""node_id":"n1""#, r#""node_id":"n2""#);
+    let err = run(&format!("{INIT_N1}\n{init_n2}\n")).unwrap_err();
+    assert_eq!(
+        err.downcast_ref(),
+        Some(&InitError::Conflicting {
+            node_id: "n1".to_string(),
+            init_node_id: "n2".to_string()
+        })
+    );
+}
+
+// acks pings only when the runtime says the input queue is empty.
+struct BatchingNode {
+    held: Vec<Message<Payload>>,
+}
+
+impl Node<(), Payload> for BatchingNode {
+    fn from_init(_: (), _: Init) -> anyhow::Result<Self> {
+        Ok(Self { held: Vec::new() })
+    }
+
+    fn step(
+        &mut self,
+        message: Message<Payload>,
+        _: &mut AuditWriter<impl Write>,
+    ) -> anyhow::Result<()> {
+        let mut reply = message.to_reply(None);
+        reply.body.payload = Payload::Pong {
+            from: "batch".to_string(),
+        };
+        self.held.push(reply);
+        Ok(())
+    }
+
+    fn idle(&mut self, writer: &mut AuditWriter<impl Write>) -> anyhow::Result<()> {
+        for reply in self.held.drain(..) {
+            reply.send(writer)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn idle_runs_before_the_input_ends() {
+    let ping2 = PING.replace(r#""msg_id":1"#, r#""msg_id":2"#);
+    let mut output = Vec::new();
+    run_node::<_, BatchingNode, _>(
+        (),
+        Cursor::new(format!("{INIT_N1}\n{PING}\n{ping2}\n")),
+        &mut output,
+    )
+    .unwrap();
+    let out: Vec<Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let acked: Vec<_> = out[1..]
+        .iter()
+        .map(|m| m["body"]["in_reply_to"].clone())
+        .collect();
+    assert_eq!(acked, [1, 2]);
+}
+
+#[test]
+fn error_bodies_go_to_on_error_not_step() {
+    let error = r#"{"src":"lin-kv","dest":"n1","body":{"type":"error","in_reply_to":4,"code":20,"text":"not found"}}"#;
+    let stats = r#"{"src":"c1","dest":"n1","body":{"type":"admin_stats","msg_id":2}}"#;
+    let out = run(&format!("{INIT_N1}\n{error}\n{stats}\n")).unwrap();
+    assert_eq!(out[1]["dest"], "lin-kv");
+    assert_eq!(out[1]["body"]["from"], "error 20: not found");
+    assert_eq!(out[2]["body"]["stats"]["received"]["error"], 1);
+    assert_eq!(out[2]["body"]["stats"]["malformed"], 0);
+}
+
+#[test]
+fn invalid_utf8_is_malformed_and_the_node_keeps_going() {
+    let stats = r#"{"src":"c1","dest":"n1","body":{"type":"admin_stats","msg_id":2}}"#;
+    let mut input = format!("{INIT_N1}\n").into_bytes();
+    input.extend_from_slice(b"\xff\xfe\n");
+    input.extend_from_slice(format!("{PING}\r\n{stats}\n").as_bytes());
+    let mut output = Vec::new();
+    run_node::<_, PingNode, _>("".to_string(), Cursor::new(input), &mut output).unwrap();
+    let out: Vec<Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(out[1]["body"]["type"], "pong");
+    assert_eq!(out[2]["body"]["stats"]["malformed"], 1);
+}
+
+#[test]
+fn frame_line_sorts_lines_by_body_type() {
+    assert!(matches!(
+        frame_line::<Payload>(INIT_N1.as_bytes()),
+        Input::Init(_)
+    ));
+    assert!(matches!(
+        frame_line::<Payload>(PING.as_bytes()),
+        Input::Message(m, e, _) if e.kind == "ping" && m.body.msg_id == Some(1)
+    ));
+    match frame_line::<Payload>(b"{\"src\":\"c1\xff\"}") {
+        Input::Malformed { line, .. } => assert_eq!(line, "{\"src\":\"c1\u{fffd}\"}"),
+        other => panic!("{other:?}"),
+    }
+}
+
+#[test]
+fn a_step_error_fails_only_that_request() {
+    let fail = r#"{"src":"c2","dest":"n1","body":{"type":"fail","msg_id":5}}"#;
+    let stats = r#"{"src":"c1","dest":"n1","body":{"type":"admin_stats","msg_id":2}}"#;
+    let out = run(&format!("{INIT_N1}\n{fail}\n{PING}\n{stats}\n")).unwrap();
+    assert_eq!(out[1]["dest"], "c2");
+    assert_eq!(out[1]["body"]["type"], "error");
+    assert_eq!(out[1]["body"]["code"], 13);
+    assert_eq!(out[1]["body"]["in_reply_to"], 5);
+    assert_eq!(out[1]["body"]["text"], "asked to fail");
+    assert_eq!(out[2]["body"]["type"], "pong");
+    assert_eq!(out[3]["body"]["stats"]["step_errors"], 1);
+    assert_eq!(out[3]["body"]["stats"]["panics"], 0);
+}
@@ -0,0 +1,188 @@
+use flyio_dist::storage::{FaultyStorage, FileStorage, MemStorage, Storage, StorageOp};
+use std::io::Write;
+use std::path::PathBuf;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn failed_calls_leave_the_backend_untouched() {
+    let mut storage = FaultyStorage::new(MemStorage::default())
+        .fail(StorageOp::Append, 1)
+        .fail(StorageOp::Commit, 0);
+    assert_eq!(storage.append("k", 10).unwrap(), 0);
+    assert!(storage.append("k", 11).is_err());
+    assert_eq!(storage.append("k", 12).unwrap(), 1);
+    assert!(storage.commit("k", 1).is_err());
+    assert_eq!(storage.committed("k").unwrap(), None);
+    storage.commit("k", 1).unwrap();
+    assert_eq!(storage.committed("k").unwrap(), Some(1));
+    assert_eq!(storage.verify().unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn reopening_after_a_torn_write() {
+    let dir = scratch_dir("torn-write");
+    {
+        let mut storage = FileStorage::open(&dir).unwrap();
+        for message in 0..3 {
+            storage.append("k", message).unwrap();
+        }
+    }
+    let log = dir.join("topics/k/log");
+    let mut file = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+    file.write_all(br#"{"offset":3,"mes"#).unwrap();
+
+    let mut storage = FileStorage::open(&dir).unwrap();
+    assert_eq!(storage.high_water_mark("k"), Some(2));
+    assert_eq!(storage.verify().unwrap(), Vec::<String>::new());
+    assert_eq!(storage.append("k", 3).unwrap(), 3);
+    let messages: Vec<_> = storage
+        .read_from("k", 0)
+        .unwrap()
+        .iter()
+        .map(|e| e.message)
+        .collect();
+    assert_eq!(messages, vec![0, 1, 2, 3]);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reopening_after_losing_only_the_newline() {
+    let dir = scratch_dir("lost-newline");
+    {
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.append("k", 5).unwrap();
+    }
+    let log = dir.join("topics/k/log");
+    let content = std::fs::read_to_string(&log).unwrap();
+    std::fs::write(&log, content.trim_end()).unwrap();
+
+    let mut storage = FileStorage::open(&dir).unwrap();
+    assert_eq!(storage.high_water_mark("k"), Some(0));
+    assert_eq!(storage.append("k", 6).unwrap(), 1);
+    assert_eq!(storage.verify().unwrap(), Vec::<String>::new());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn reopening_indexes_every_topic() {
+    let dir = scratch_dir("many-topics");
+    {
+        let mut storage = FileStorage::open(&dir).unwrap();
+        for topic in 0..40 {
+            for message in 0..topic % 5 + 1 {
+                storage
+                    .append(&format!("t/{topic}"), topic * 100 + message)
+                    .unwrap();
+            }
+        }
+    }
+    let mut storage = FileStorage::open(&dir).unwrap();
+    for topic in 0..40 {
+        let name = format!("t/{topic}");
+        assert_eq!(storage.high_water_mark(&name), Some(topic % 5), "{name}");
+        let last = storage.read_from(&name, topic % 5).unwrap();
+        assert_eq!(last[0].message, topic * 100 + topic % 5, "{name}");
+    }
+    assert_eq!(storage.verify().unwrap(), Vec::<String>::new());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn io_is_counted_per_operation() {
+    let dir = scratch_dir("io-stats");
+    let line_len = |offset: usize, message: usize| {
+        format!("{{\"offset\":{offset},\"message\":{message}}}\n").len() as u64
+    };
+    {
+        let mut storage = FileStorage::open(&dir).unwrap();
+        storage.append("k", 10).unwrap();
+        storage.append("k", 200).unwrap();
+        storage.append("j", 3).unwrap();
+        storage.sync().unwrap();
+        storage.sync().unwrap();
+        storage.commit("k", 1).unwrap();
+        assert_eq!(storage.read_from("k", 1).unwrap().len(), 1);
+        assert_eq!(storage.committed("k").unwrap(), Some(1));
+
+        let io = storage.io_stats();
+        let appended = line_len(0, 10) + line_len(1, 200) + line_len(0, 3);
+        assert_eq!(io.ops["append"].calls, 3);
+        assert_eq!(io.ops["append"].bytes_written, appended);
+        // two logs had appends before the first sync, none before the second.
+        assert_eq!(io.ops["sync"].calls, 2);
+        assert_eq!(io.ops["sync"].fsyncs, 2);
+        assert_eq!(io.ops["commit"].bytes_written, 2);
+        assert_eq!(io.ops["read_from"].bytes_read, line_len(1, 200));
+        assert_eq!(io.ops["committed"].bytes_read, 2);
+        assert_eq!(io.bytes_written, appended + 2);
+        assert_eq!(io.fsyncs, 2);
+    }
+    // reopening reads every log once.
+    let storage = FileStorage::open(&dir).unwrap();
+    let io = storage.io_stats();
+    assert_eq!(
+        io.ops["open"].bytes_read,
+        line_len(0, 10) + line_len(1, 200) + line_len(0, 3)
+    );
+    assert_eq!(io.bytes_written, 0);
+    assert_eq!(MemStorage::default().io_stats().bytes_written, 0);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_corrupt_commit_file_is_a_problem_not_an_error() {
+    let dir = scratch_dir("corrupt-commit");
+    let mut storage = FileStorage::open(&dir).unwrap();
+    storage.commit("good", 3).unwrap();
+    storage.commit("bad", 1).unwrap();
+    std::fs::write(dir.join("commits/bad"), "x\n").unwrap();
+
+    assert_eq!(storage.commits().unwrap(), vec![("good".to_string(), 3)]);
+    assert!(storage.committed("bad").is_err());
+    let problems = storage.verify().unwrap();
+    assert_eq!(problems.len(), 1, "{problems:?}");
+    assert!(problems[0].starts_with("commit bad:"), "{problems:?}");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn a_corrupt_log_line_is_a_problem_not_an_error() {
+    let dir = scratch_dir("corrupt-log");
+    {
+        let mut storage = FileStorage::open(&dir).unwrap();
+        for message in 0..3 {
+            storage.append("k", message).unwrap();
+        }
+    }
+    let log = dir.join("topics/k/log");
+    let contents = std::fs::read(&log).unwrap();
+    let first = contents.iter().position(|b| *b == b'\n').unwrap() + 1;
+    let second = first + contents[first..].iter().position(|b| *b == b'\n').unwrap();
+    let mut corrupted = contents[..first].to_vec();
+    corrupted.extend_from_slice(b"\xff{\"offset\":1,");
+    corrupted.extend_from_slice(&contents[second..]);
+    std::fs::write(&log, corrupted).unwrap();
+
+    let mut storage = FileStorage::open(&dir).unwrap();
+    assert_eq!(storage.high_water_mark("k"), Some(2));
+    let messages: Vec<_> = storage
+        .read_from("k", 0)
+        .unwrap()
+        .iter()
+        .map(|e| e.message)
+        .collect();
+    assert_eq!(messages, vec![0, 2]);
+    let problems = storage.verify().unwrap();
+    assert!(
+        problems
+            .iter()
+            .any(|p| p.starts_with(&format!("k: bad entry at byte {first}"))),
+        "{problems:?}"
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
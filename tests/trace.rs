@@ -0,0 +1,99 @@
+//! Needs `cargo test --features trace`.
+#![cfg(feature = "trace")]
+
+use flyio_dist::trace::CHUNK;
+use serde_json::{Value, json};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// runs echo as n2 in a scratch dir on `requests` and returns its trace.
+fn trace_of(name: &str, requests: impl IntoIterator<Item = Value>) -> Value {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        let init = json!({"src": "c0", "dest": "n2", "body": {"type": "init", "msg_id": 1, "node_id": "n2", "node_ids": ["n1", "n2"]}});
+        for line in std::iter::once(init).chain(requests) {
+            writeln!(stdin, "{line}").unwrap();
+        }
+    }
+    assert!(child.wait().unwrap().success());
+
+    let trace = serde_json::from_str(
+        &std::fs::read_to_string(dir.join("data/n2/trace/trace.json")).unwrap(),
+    )
+    .unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+    trace
+}
+
+#[test]
+fn writes_a_chrome_trace_at_shutdown() {
+    let trace = trace_of(
+        "trace",
+        [
+            json!({"src": "c1", "dest": "n2", "body": {"type": "echo", "msg_id": 1, "echo": "x", "trace_id": "op-1"}}),
+        ],
+    );
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events[0]["args"]["name"], "n2");
+    let step = events.iter().find(|e| e["name"] == "echo").unwrap();
+    assert_eq!(step["ph"], "X");
+    assert_eq!(step["pid"], 1);
+    assert_eq!(step["args"]["src"], "c1");
+    assert_eq!(step["args"]["trace_id"], "op-1");
+    assert!(events.iter().any(|e| e["name"] == "queue"));
+}
+
+#[test]
+fn long_traces_are_written_in_chunks() {
+    // two events per request: enough for a couple of chunks and a remainder.
+    let requests = CHUNK + 5;
+    let trace = trace_of(
+        "trace-chunks",
+        (1..=requests).map(|i| {
+            json!({"src": "c1", "dest": "n2", "body": {"type": "echo", "msg_id": i, "echo": "x"}})
+        }),
+    );
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 1 + 2 * requests);
+    assert_eq!(events[0]["name"], "process_name");
+    let last = events.iter().rev().find(|e| e["name"] == "echo").unwrap();
+    assert_eq!(last["args"]["msg_id"], requests);
+}
+
+#[test]
+fn an_unwritable_trace_still_leaves_the_summary() {
+    let dir = std::env::temp_dir().join(format!("flyio-dist-{}-no-trace", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // a file where the trace dir should go.
+    std::fs::create_dir_all(dir.join("data/n1")).unwrap();
+    std::fs::write(dir.join("data/n1/trace"), "").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_echo"))
+        .current_dir(&dir)
+        .env("FLYIO_SUMMARY_DIR", &dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        let init = json!({"src": "c0", "dest": "n1", "body": {"type": "init", "msg_id": 1, "node_id": "n1", "node_ids": ["n1"]}});
+        writeln!(stdin, "{init}").unwrap();
+    }
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("write trace:"));
+    assert!(dir.join("n1.json").is_file());
+    std::fs::remove_dir_all(dir).unwrap();
+}
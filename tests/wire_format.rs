@@ -0,0 +1,315 @@
+//! Golden wire-format tests: each line of `tests/golden/<name>.jsonl` is a
+//! message as Maelstrom documents it. It must deserialize into the crate's
+//! types and serialize back to the same JSON, so a rename or flatten change
+//! that alters the wire format fails here.
+
+use flyio_dist::audit::{AuditWriter, Envelope};
+use flyio_dist::rng::Rng;
+use flyio_dist::workloads::{broadcast, echo, kafka, unique_ids};
+use flyio_dist::{Body, ErrorPayload, InitPayload, Message, WithExtra, send_to_each, send_to_many};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+// absent and null optional fields mean the same thing on the wire.
+fn without_nulls(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, without_nulls(v)))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+fn round_trip<P: Serialize + DeserializeOwned>(name: &str) -> Vec<Message<P>> {
+    let path = format!("{}/tests/golden/{name}.jsonl", env!("CARGO_MANIFEST_DIR"));
+    let fixture = std::fs::read_to_string(&path).unwrap();
+    let mut messages = Vec::new();
+    for line in fixture.lines() {
+        let msg: Message<P> =
+            serde_json::from_str(line).unwrap_or_else(|e| panic!("{name}: {line}: {e}"));
+        let expected: Value = serde_json::from_str(line).unwrap();
+        let actual = serde_json::to_value(&msg).unwrap();
+        assert_eq!(without_nulls(actual), expected, "{name}: {line}");
+        messages.push(msg);
+    }
+    messages
+}
+
+#[test]
+fn init() {
+    let msgs = round_trip::<InitPayload>("init");
+    let InitPayload::Init(init) = &msgs[0].body.payload else {
+        panic!("{:?}", msgs[0]);
+    };
+    assert_eq!(init.node_id, "n3");
+    assert_eq!(init.node_ids.len(), 3);
+}
+
+#[test]
+fn error() {
+    let msgs = round_trip::<ErrorPayload>("error");
+    let ErrorPayload::Error { code, .. } = &msgs[0].body.payload;
+    assert_eq!(*code, flyio_dist::error_code::TEMPORARILY_UNAVAILABLE);
+}
+
+#[test]
+fn echo() {
+    round_trip::<echo::Payload>("echo");
+}
+
+#[test]
+fn unique_ids() {
+    let msgs = round_trip::<unique_ids::Payload>("unique_ids");
+    assert_eq!(
+        msgs[1].body.payload,
+        unique_ids::Payload::GenerateOk {
+            id: flyio_dist::ids::Id::Num(123)
+        }
+    );
+}
+
+#[test]
+fn broadcast() {
+    let msgs = round_trip::<broadcast::Payload>("broadcast");
+    let broadcast::Payload::Broadcast { messages, sent_at } = &msgs[0].body.payload else {
+        panic!("{:?}", msgs[0]);
+    };
+    assert_eq!(messages.as_slice(), [1000]);
+    assert_eq!(*sent_at, None);
+}
+
+#[test]
+fn kafka() {
+    let msgs = round_trip::<kafka::Payload>("kafka");
+    assert_eq!(
+        msgs[0].body.payload,
+        kafka::Payload::Send {
+            topic: "k1".to_string(),
+            message: 123
+        }
+    );
+}
+
+#[test]
+fn unknown_fields_round_trip_with_extra() {
+    let line =
+        r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"x","trace":"t1"}}"#;
+    let msg: Message<WithExtra<echo::Payload>> = serde_json::from_str(line).unwrap();
+    assert_eq!(
+        msg.body.payload.payload,
+        echo::Payload::Echo {
+            echo: "x".to_string()
+        }
+    );
+    assert_eq!(msg.body.payload.extra.keys().collect::<Vec<_>>(), ["trace"]);
+    let actual = serde_json::to_value(&msg).unwrap();
+    assert_eq!(
+        without_nulls(actual),
+        serde_json::from_str::<Value>(line).unwrap()
+    );
+}
+
+#[test]
+fn send_to_many_writes_what_send_would() {
+    let body = Body {
+        msg_id: Some(3),
+        in_reply_to: None,
+        payload: broadcast::Payload::Broadcast {
+            messages: broadcast::Messages::One { message: 9 },
+            sent_at: Some(17),
+        },
+    };
+    let mut many = Vec::new();
+    send_to_many(
+        &mut AuditWriter::new(&mut many),
+        "n1",
+        ["n2", "n\"3"],
+        &body,
+    )
+    .unwrap();
+    let dests: Vec<Value> = std::str::from_utf8(&many)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str::<Value>(l).unwrap()["dest"].clone())
+        .collect();
+    assert_eq!(dests, ["n2", "n\"3"]);
+    let mut one_by_one = Vec::new();
+    for peer in ["n2", "n\"3"] {
+        let msg = Message {
+            src: "n1".to_string(),
+            dst: peer.to_string(),
+            body: body.clone(),
+        };
+        msg.send(&mut AuditWriter::new(&mut one_by_one)).unwrap();
+    }
+    assert_eq!(
+        String::from_utf8(many).unwrap(),
+        String::from_utf8(one_by_one).unwrap()
+    );
+}
+
+#[test]
+fn send_to_each_builds_a_body_per_peer() {
+    let mut out = Vec::new();
+    send_to_each(
+        &mut AuditWriter::new(&mut out),
+        "n1",
+        ["n2", "n3"],
+        |peer| Body {
+            msg_id: None,
+            in_reply_to: None,
+            payload: echo::Payload::Echo {
+                echo: format!("for {peer}"),
+            },
+        },
+    )
+    .unwrap();
+    let sent: Vec<(String, String)> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|l| {
+            let msg: Message<echo::Payload> = serde_json::from_str(l).unwrap();
+            assert_eq!(msg.src, "n1");
+            let echo::Payload::Echo { echo } = msg.body.payload else {
+                panic!("{l}");
+            };
+            (msg.dst, echo)
+        })
+        .collect();
+    assert_eq!(
+        sent,
+        [
+            ("n2".to_string(), "for n2".to_string()),
+            ("n3".to_string(), "for n3".to_string())
+        ]
+    );
+}
+
+// what the fuzz target (fuzz/fuzz_targets/envelope.rs) checks, on a fixed
+// set of mutated golden lines so it runs with the other tests.
+fn reparses_the_same<P: Serialize + DeserializeOwned + std::fmt::Debug + PartialEq>(line: &[u8]) {
+    let _ = Envelope::peek(line);
+    let Ok(msg) = serde_json::from_slice::<Message<P>>(line) else {
+        return;
+    };
+    let again = serde_json::to_vec(&msg).unwrap();
+    let reparsed: Message<P> = serde_json::from_slice(&again).unwrap();
+    assert_eq!(
+        reparsed.body.payload,
+        msg.body.payload,
+        "{}",
+        String::from_utf8_lossy(line)
+    );
+}
+
+#[test]
+fn mutated_lines_parse_or_fail_cleanly() {
+    const PIECES: &[&[u8]] = &[
+        b"\"",
+        b"{",
+        b"}",
+        b",",
+        b":",
+        b"1e400",
+        b"-0",
+        b"null",
+        b"\"type\":\"echo\"",
+    ];
+    let mut rng = Rng::from_seed(0x5eed);
+    for name in ["echo", "broadcast", "kafka", "init", "error", "unique_ids"] {
+        let path = format!("{}/tests/golden/{name}.jsonl", env!("CARGO_MANIFEST_DIR"));
+        for line in std::fs::read_to_string(&path).unwrap().lines() {
+            for _ in 0..300 {
+                let mut bytes = line.as_bytes().to_vec();
+                let at = rng.below(bytes.len() as u64) as usize;
+                match rng.below(3) {
+                    0 => {
+                        bytes.remove(at);
+                    }
+                    1 => {
+                        let piece = rng.choose(PIECES).unwrap();
+                        bytes.splice(at..at, piece.iter().copied());
+                    }
+                    _ => bytes[at] = b' ' + rng.below(95) as u8,
+                }
+                reparses_the_same::<WithExtra<echo::Payload>>(&bytes);
+                reparses_the_same::<WithExtra<broadcast::Payload>>(&bytes);
+                reparses_the_same::<kafka::Payload>(&bytes);
+            }
+        }
+    }
+}
+
+#[test]
+fn extra_floats_survive_a_hop() {
+    // found by the fuzz target: without serde_json's float_roundtrip, the
+    // last digit of a pass-through float changed on every hop.
+    let line =
+        br#"{"src":"c1","dest":"n1","body":{"type":"echo","echo":"x","n":1.3333333333333333e25}}"#;
+    let msg: Message<WithExtra<echo::Payload>> = serde_json::from_slice(line).unwrap();
+    let again = serde_json::to_string(&msg).unwrap();
+    assert!(again.contains(r#""n":1.3333333333333333e25"#), "{again}");
+}
+
+#[test]
+fn service_replies() {
+    // lin-kv, seq-kv and friends have no payload types here; any body must
+    // still parse, ids included.
+    let msgs = round_trip::<Value>("services");
+    let in_reply_to: Vec<_> = msgs.iter().map(|m| m.body.in_reply_to).collect();
+    assert_eq!(in_reply_to, [1, 2, 3, 4, u64::MAX, 4_294_967_297].map(Some));
+    assert_eq!(msgs[5].body.msg_id, Some(4_294_967_296));
+    let errors: Vec<Message<ErrorPayload>> = msgs[3..5]
+        .iter()
+        .map(|m| serde_json::from_value(serde_json::to_value(m).unwrap()).unwrap())
+        .collect();
+    assert!(matches!(
+        errors[0].body.payload,
+        ErrorPayload::Error {
+            code: flyio_dist::error_code::KEY_DOES_NOT_EXIST,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn ids_are_read_leniently() {
+    let parse = |ids: &str| {
+        let line = format!(r#"{{"src":"a","dest":"b","body":{{"type":"echo","echo":"x"{ids}}}}}"#);
+        let msg = serde_json::from_str::<Message<echo::Payload>>(&line)
+            .map(|m| (m.body.msg_id, m.body.in_reply_to));
+        let peeked = Envelope::peek(line.as_bytes()).map(|e| (e.msg_id, e.in_reply_to));
+        assert_eq!(msg.as_ref().ok().copied(), peeked, "{line}");
+        msg
+    };
+    assert_eq!(parse("").unwrap(), (None, None));
+    assert_eq!(
+        parse(r#","msg_id":null,"in_reply_to":null"#).unwrap(),
+        (None, None)
+    );
+    assert_eq!(
+        parse(r#","msg_id":3.0,"in_reply_to":"7""#).unwrap(),
+        (Some(3), Some(7))
+    );
+    for bad in [
+        r#","msg_id":-1"#,
+        r#","msg_id":1.5"#,
+        r#","msg_id":"x""#,
+        r#","msg_id":18446744073709551616"#,
+        r#","msg_id":[1]"#,
+    ] {
+        assert!(parse(bad).is_err(), "{bad}");
+    }
+    // whatever was read, ids go out as numbers.
+    let line = r#"{"src":"a","dest":"b","body":{"type":"echo","echo":"x","msg_id":"9"}}"#;
+    let msg: Message<echo::Payload> = serde_json::from_str(line).unwrap();
+    assert!(
+        serde_json::to_string(&msg)
+            .unwrap()
+            .contains(r#""msg_id":9"#)
+    );
+}